@@ -1,109 +1,705 @@
-use crate::lex::Token;
+use crate::lex::{assignment, is_name, lex, Quote, Token, Word, RESERVED};
+use std::fmt;
+use std::rc::Rc;
 
-type Command = (Vec<String>, bool); // .1: Whether run in background
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectKind {
+    In,        // <
+    Out,       // >
+    Append,    // >>
+    Clobber,   // >|
+    ReadWrite, // <>
+    DupIn,     // <&
+    DupOut,    // >&
+    HereDoc,   // << and <<-
+}
 
 #[derive(Debug, Clone)]
-pub enum Proc {
-    SubProc(Command),
-    Pipe(Vec<Proc>),
-    RRed(Box<Proc>, String),
-    LRed(Box<Proc>, String),
+pub struct Redirect {
+    pub fd: Option<i32>,
+    pub kind: RedirectKind,
+    pub target: Word, // The body for here-documents
+}
+
+impl Redirect {
+    /// The file descriptor being redirected
+    pub fn fd(&self) -> i32 {
+        self.fd.unwrap_or(match self.kind {
+            RedirectKind::In
+            | RedirectKind::ReadWrite
+            | RedirectKind::DupIn
+            | RedirectKind::HereDoc => 0,
+            RedirectKind::Out
+            | RedirectKind::Append
+            | RedirectKind::Clobber
+            | RedirectKind::DupOut => 1,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SimpleCommand {
+    pub assigns: Vec<(String, Word)>, // NAME=value before the command name
+    pub args: Vec<Word>,
+    pub redirects: Vec<Redirect>,
 }
 
 #[derive(Debug, Clone)]
-enum Op {
-    Pipe,
-    RRed,
-    LRed,
-}
-
-fn read_until_op(it: &mut std::slice::Iter<Token>) -> Option<(Option<Op>, Command)> {
-    let mut res = Vec::new();
-    let mut is_background = false;
-    let mut op = None;
-    while let Some(tok) = it.next() {
-        match tok {
-            Token::Str(cmd) => res.push(cmd.clone()),
-            Token::Pipe => {
-                op = Some(Op::Pipe);
-                break;
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: List,
+}
+
+#[derive(Debug, Clone)]
+pub enum Compound {
+    Brace(List),
+    Subshell(List),
+    For(String, Option<Vec<Word>>, List),
+    Case(Word, Vec<CaseItem>),
+    If(Vec<(List, List)>, Option<List>), // (condition, body) for `if` and every `elif`
+    While(List, List),
+    Until(List, List),
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(Compound, Vec<Redirect>),
+    Function(String, Rc<Command>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    pub time: Option<TimeFormat>, // time p1 | p2
+    pub bang: bool,               // ! p1 | p2
+    pub cmds: Vec<Command>,
+}
+
+/// How `time` reports: with `TIMEFORMAT`, or in the POSIX format for `time -p`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    Default,
+    Posix,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AndOrOp {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(AndOrOp, Pipeline)>,
+}
+
+pub type List = Vec<(AndOr, bool)>; // .1: Whether run in background
+
+// Printing commands back as shell source, on one line, e.g. for job descriptions
+
+fn write_words(f: &mut fmt::Formatter<'_>, words: &[Word], sep: &str) -> fmt::Result {
+    for (i, w) in words.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", sep)?;
+        }
+        write!(f, "{}", w)?;
+    }
+    Ok(())
+}
+
+// Every item ends with `;` or `&`, as in `{ a; b & }`
+fn write_list(f: &mut fmt::Formatter<'_>, list: &List) -> fmt::Result {
+    for (i, (and_or, bg)) in list.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "{}{}", and_or, if *bg { " &" } else { ";" })?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(fd) = self.fd {
+            write!(f, "{}", fd)?;
+        }
+        let op = match self.kind {
+            RedirectKind::In => "<",
+            RedirectKind::Out => ">",
+            RedirectKind::Append => ">>",
+            RedirectKind::Clobber => ">|",
+            RedirectKind::ReadWrite => "<>",
+            RedirectKind::DupIn => "<&",
+            RedirectKind::DupOut => ">&",
+            // The delimiter is not kept, and the body does not fit on one line
+            RedirectKind::HereDoc => return write!(f, "<<EOF"),
+        };
+        write!(f, "{}{}", op, self.target)
+    }
+}
+
+impl fmt::Display for SimpleCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.assigns.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
             }
-            Token::LeftRedirect => {
-                op = Some(Op::LRed);
-                break;
+            write!(f, "{}={}", name, value)?;
+        }
+        if !self.assigns.is_empty() && !self.args.is_empty() {
+            write!(f, " ")?;
+        }
+        write_words(f, &self.args, " ")?;
+        for (i, r) in self.redirects.iter().enumerate() {
+            if i > 0 || !self.args.is_empty() || !self.assigns.is_empty() {
+                write!(f, " ")?;
             }
-            Token::RightRedirect => {
-                op = Some(Op::RRed);
-                break;
+            write!(f, "{}", r)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Compound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compound::Brace(list) => {
+                write!(f, "{{ ")?;
+                write_list(f, list)?;
+                write!(f, " }}")
+            }
+            Compound::Subshell(list) => {
+                write!(f, "( ")?;
+                write_list(f, list)?;
+                write!(f, " )")
+            }
+            Compound::For(name, words, body) => {
+                write!(f, "for {}", name)?;
+                if let Some(words) = words {
+                    write!(f, " in ")?;
+                    write_words(f, words, " ")?;
+                }
+                write!(f, "; do ")?;
+                write_list(f, body)?;
+                write!(f, " done")
             }
-            Token::Background => {
-                is_background = true;
-            }
-        }
-    }
-    if res.is_empty() {
-        None
-    } else {
-        Some((op, (res, is_background)))
-    }
-}
-
-pub fn parse(toks: Vec<Token>) -> Option<Proc> {
-    // Parse
-    // All operators are left-associative and have the same precedence
-    // p1 | p2 > p3 == (p1 | p2) > p3
-    // p1 > p2 | p3 == (p1 > p2) | p3
-    let mut cur = Proc::SubProc((Vec::new(), false));
-    let mut it = toks.iter();
-    let mut cur_op = None;
-    let mut cur_pipes = Vec::new();
-    while let Some((op, tok)) = read_until_op(&mut it) {
-        match cur_op {
-            None => {
-                // Start
-                cur = Proc::SubProc(tok.clone());
-            }
-            Some(Op::Pipe) => {
-                // cur | tok
-                cur_pipes.push(Proc::SubProc(tok));
-                match op {
-                    Some(Op::Pipe) => {}
-                    _ => {
-                        // Need to push cur_pipes
-                        let mut cp = vec![cur];
-                        cp.extend(cur_pipes.clone());
-                        cur = Proc::Pipe(cp);
-                        cur_pipes.clear();
+            Compound::Case(word, items) => {
+                write!(f, "case {} in", word)?;
+                for item in items {
+                    write!(f, " ")?;
+                    write_words(f, &item.patterns, "|")?;
+                    if item.body.is_empty() {
+                        write!(f, ");;")?;
+                    } else {
+                        write!(f, ") ")?;
+                        write_list(f, &item.body)?;
+                        write!(f, ";")?;
                     }
                 }
+                write!(f, " esac")
             }
-            Some(Op::RRed) => {
-                // cur > tok
-                if tok.0.len() != 1 {
-                    // Not a file
-                    return None;
+            Compound::If(branches, otherwise) => {
+                for (i, (cond, body)) in branches.iter().enumerate() {
+                    write!(f, "{} ", if i == 0 { "if" } else { " elif" })?;
+                    write_list(f, cond)?;
+                    write!(f, " then ")?;
+                    write_list(f, body)?;
+                }
+                if let Some(body) = otherwise {
+                    write!(f, " else ")?;
+                    write_list(f, body)?;
+                }
+                write!(f, " fi")
+            }
+            Compound::While(cond, body) | Compound::Until(cond, body) => {
+                let kw = if matches!(self, Compound::While(..)) {
+                    "while"
                 } else {
-                    // A file
-                    cur = Proc::RRed(Box::new(cur), tok.0[0].clone());
+                    "until"
+                };
+                write!(f, "{} ", kw)?;
+                write_list(f, cond)?;
+                write!(f, " do ")?;
+                write_list(f, body)?;
+                write!(f, " done")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Simple(c) => write!(f, "{}", c),
+            Command::Compound(c, redirects) => {
+                write!(f, "{}", c)?;
+                for r in redirects {
+                    write!(f, " {}", r)?;
+                }
+                Ok(())
+            }
+            Command::Function(name, body) => write!(f, "{}() {}", name, body),
+        }
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.time {
+            Some(TimeFormat::Default) => write!(f, "time ")?,
+            Some(TimeFormat::Posix) => write!(f, "time -p ")?,
+            None => {}
+        }
+        if self.bang {
+            write!(f, "! ")?;
+        }
+        for (i, cmd) in self.cmds.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", cmd)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AndOr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (op, p) in &self.rest {
+            let op = match op {
+                AndOrOp::And => "&&",
+                AndOrOp::Or => "||",
+            };
+            write!(f, " {} {}", op, p)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnexpectedEof,
+    Unexpected(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedEof => write!(f, "unexpected end of input"),
+            ParseError::Unexpected(tok) => write!(f, "unexpected token `{}`", tok),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Words that end a compound list when found in command position
+const LIST_END: &[&str] = &["then", "else", "elif", "fi", "do", "done", "esac", "}"];
+
+fn word_is(w: &Word, kw: &str) -> bool {
+    matches!(&w.0[..], [p] if p.quote == Quote::Unquoted && p.text == kw)
+}
+
+struct Parser {
+    toks: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.toks.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let tok = self.toks.get(self.pos).cloned();
+        self.pos += 1;
+        tok
+    }
+
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            None => ParseError::UnexpectedEof,
+            Some(tok) => ParseError::Unexpected(tok.to_string()),
+        }
+    }
+
+    fn eat(&mut self, tok: &Token) -> bool {
+        if self.peek() == Some(tok) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, tok: &Token) -> Result<(), ParseError> {
+        if self.eat(tok) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn is_reserved(&self, kw: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if word_is(w, kw))
+    }
+
+    fn eat_reserved(&mut self, kw: &str) -> bool {
+        if self.is_reserved(kw) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_reserved(&mut self, kw: &str) -> Result<(), ParseError> {
+        if self.eat_reserved(kw) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn expect_word(&mut self) -> Result<Word, ParseError> {
+        match self.peek() {
+            Some(Token::Word(w)) => {
+                let w = w.clone();
+                self.pos += 1;
+                Ok(w)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn linebreak(&mut self) {
+        while self.eat(&Token::Newline) {}
+    }
+
+    fn at_list_end(&self) -> bool {
+        match self.peek() {
+            None | Some(Token::RParen) | Some(Token::DSemi) => true,
+            Some(Token::Word(w)) => LIST_END.iter().any(|kw| word_is(w, kw)),
+            _ => false,
+        }
+    }
+
+    // compound_list : linebreak term separator?
+    // term          : and_or (separator and_or)*
+    fn compound_list(&mut self) -> Result<List, ParseError> {
+        let mut list = Vec::new();
+        loop {
+            self.linebreak();
+            if self.at_list_end() {
+                break;
+            }
+            let and_or = self.and_or()?;
+            match self.peek() {
+                Some(Token::Background) => list.push((and_or, true)),
+                Some(Token::Semi) | Some(Token::Newline) => list.push((and_or, false)),
+                _ => {
+                    list.push((and_or, false));
+                    break;
                 }
             }
-            Some(Op::LRed) => {
-                // cur < tok
-                if tok.0.len() != 1 {
-                    // Not a file
-                    return None;
+            self.pos += 1;
+        }
+        Ok(list)
+    }
+
+    // A compound list that must not be empty
+    fn body(&mut self) -> Result<List, ParseError> {
+        let list = self.compound_list()?;
+        if list.is_empty() {
+            Err(self.unexpected())
+        } else {
+            Ok(list)
+        }
+    }
+
+    // and_or : pipeline (('&&' | '||') linebreak pipeline)*
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.peek() {
+                Some(Token::AndIf) => AndOrOp::And,
+                Some(Token::OrIf) => AndOrOp::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.linebreak();
+            rest.push((op, self.pipeline()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    // pipeline : ('time' '-p'?)? '!'? command ('|' linebreak command)*
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let time = if self.eat_reserved("time") {
+            if self.eat_reserved("-p") {
+                Some(TimeFormat::Posix)
+            } else {
+                Some(TimeFormat::Default)
+            }
+        } else {
+            None
+        };
+        let bang = self.eat_reserved("!");
+        let mut cmds = vec![self.command()?];
+        while self.eat(&Token::Pipe) {
+            self.linebreak();
+            cmds.push(self.command()?);
+        }
+        Ok(Pipeline { time, bang, cmds })
+    }
+
+    // command : simple_command | compound_command redirect_list? | function_definition
+    fn command(&mut self) -> Result<Command, ParseError> {
+        let kw = match self.peek() {
+            Some(Token::LParen) => "(".to_string(),
+            Some(Token::Word(w)) if !w.is_quoted() => w.literal(),
+            _ => return self.simple_command(),
+        };
+        let compound = match kw.as_str() {
+            "(" => {
+                self.pos += 1;
+                let list = self.body()?;
+                self.expect(&Token::RParen)?;
+                Compound::Subshell(list)
+            }
+            "{" => {
+                self.pos += 1;
+                let list = self.body()?;
+                self.expect_reserved("}")?;
+                Compound::Brace(list)
+            }
+            "if" => self.if_clause()?,
+            "while" | "until" => {
+                self.pos += 1;
+                let cond = self.body()?;
+                let body = self.do_group()?;
+                if kw == "while" {
+                    Compound::While(cond, body)
                 } else {
-                    // A file
-                    cur = Proc::LRed(Box::new(cur), tok.0[0].clone());
+                    Compound::Until(cond, body)
+                }
+            }
+            "for" => self.for_clause()?,
+            "case" => self.case_clause()?,
+            _ => return self.simple_command(),
+        };
+        let redirects = self.redirect_list()?;
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    fn if_clause(&mut self) -> Result<Compound, ParseError> {
+        self.pos += 1;
+        let mut branches = Vec::new();
+        loop {
+            let cond = self.body()?;
+            self.expect_reserved("then")?;
+            branches.push((cond, self.body()?));
+            if !self.eat_reserved("elif") {
+                break;
+            }
+        }
+        let otherwise = if self.eat_reserved("else") {
+            Some(self.body()?)
+        } else {
+            None
+        };
+        self.expect_reserved("fi")?;
+        Ok(Compound::If(branches, otherwise))
+    }
+
+    fn do_group(&mut self) -> Result<List, ParseError> {
+        self.expect_reserved("do")?;
+        let body = self.body()?;
+        self.expect_reserved("done")?;
+        Ok(body)
+    }
+
+    // for_clause : For name linebreak (in word* sequential_sep)? do_group
+    fn for_clause(&mut self) -> Result<Compound, ParseError> {
+        self.pos += 1;
+        let name = match self.peek() {
+            Some(Token::Word(w)) if !w.is_quoted() && is_name(&w.literal()) => w.literal(),
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        self.linebreak();
+        let words = if self.eat_reserved("in") {
+            let mut words = Vec::new();
+            while let Some(Token::Word(w)) = self.peek() {
+                words.push(w.clone());
+                self.pos += 1;
+            }
+            if !self.eat(&Token::Semi) && !self.eat(&Token::Newline) {
+                return Err(self.unexpected());
+            }
+            Some(words)
+        } else {
+            self.eat(&Token::Semi);
+            None
+        };
+        self.linebreak();
+        Ok(Compound::For(name, words, self.do_group()?))
+    }
+
+    // case_clause : Case WORD linebreak in linebreak (pattern ')' compound_list ';;')* Esac
+    fn case_clause(&mut self) -> Result<Compound, ParseError> {
+        self.pos += 1;
+        let word = self.expect_word()?;
+        self.linebreak();
+        self.expect_reserved("in")?;
+        self.linebreak();
+        let mut items = Vec::new();
+        while !self.eat_reserved("esac") {
+            self.eat(&Token::LParen);
+            let mut patterns = vec![self.expect_word()?];
+            while self.eat(&Token::Pipe) {
+                patterns.push(self.expect_word()?);
+            }
+            self.expect(&Token::RParen)?;
+            let body = self.compound_list()?;
+            items.push(CaseItem { patterns, body });
+            if !self.eat(&Token::DSemi) {
+                // The last item may omit `;;`
+                self.linebreak();
+                self.expect_reserved("esac")?;
+                break;
+            }
+            self.linebreak();
+        }
+        Ok(Compound::Case(word, items))
+    }
+
+    fn is_redirect(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(
+                Token::IoNumber(_)
+                    | Token::LeftRedirect
+                    | Token::RightRedirect
+                    | Token::Append
+                    | Token::Clobber
+                    | Token::ReadWrite
+                    | Token::DupIn
+                    | Token::DupOut
+                    | Token::HereDoc(_)
+            )
+        )
+    }
+
+    // io_redirect : IO_NUMBER? op WORD
+    fn redirect(&mut self) -> Result<Redirect, ParseError> {
+        let fd = match self.peek() {
+            Some(Token::IoNumber(n)) => {
+                let n = *n;
+                self.pos += 1;
+                Some(n)
+            }
+            _ => None,
+        };
+        let kind = match self.next() {
+            Some(Token::LeftRedirect) => RedirectKind::In,
+            Some(Token::RightRedirect) => RedirectKind::Out,
+            Some(Token::Append) => RedirectKind::Append,
+            Some(Token::Clobber) => RedirectKind::Clobber,
+            Some(Token::ReadWrite) => RedirectKind::ReadWrite,
+            Some(Token::DupIn) => RedirectKind::DupIn,
+            Some(Token::DupOut) => RedirectKind::DupOut,
+            Some(Token::HereDoc(doc)) => {
+                // The delimiter has been consumed by the lexer already
+                self.expect_word()?;
+                return Ok(Redirect {
+                    fd,
+                    kind: RedirectKind::HereDoc,
+                    target: doc.body,
+                });
+            }
+            _ => {
+                self.pos -= 1;
+                return Err(self.unexpected());
+            }
+        };
+        let target = self.expect_word()?;
+        Ok(Redirect { fd, kind, target })
+    }
+
+    fn redirect_list(&mut self) -> Result<Vec<Redirect>, ParseError> {
+        let mut redirects = Vec::new();
+        while self.is_redirect() {
+            redirects.push(self.redirect()?);
+        }
+        Ok(redirects)
+    }
+
+    // simple_command      : (WORD | io_redirect)+
+    // function_definition : fname '(' ')' linebreak compound_command
+    fn simple_command(&mut self) -> Result<Command, ParseError> {
+        if let Some(Token::Word(w)) = self.peek() {
+            if !w.is_quoted() && RESERVED.contains(&w.literal().as_str()) {
+                return Err(self.unexpected());
+            }
+            let is_function = !w.is_quoted()
+                && is_name(&w.literal())
+                && self.toks.get(self.pos + 1) == Some(&Token::LParen)
+                && self.toks.get(self.pos + 2) == Some(&Token::RParen);
+            if is_function {
+                let name = w.literal();
+                self.pos += 3;
+                self.linebreak();
+                let body = self.command()?;
+                if let Command::Compound(..) = body {
+                    return Ok(Command::Function(name, Rc::new(body)));
+                }
+                return Err(ParseError::Unexpected(name));
+            }
+        }
+        let mut cmd = SimpleCommand::default();
+        loop {
+            if let Some(Token::Word(w)) = self.peek() {
+                // Assignments come before the command name, after it they are arguments
+                match assignment(w).filter(|_| cmd.args.is_empty()) {
+                    Some(assign) => cmd.assigns.push(assign),
+                    None => cmd.args.push(w.clone()),
                 }
+                self.pos += 1;
+            } else if self.is_redirect() {
+                cmd.redirects.push(self.redirect()?);
+            } else {
+                break;
             }
         }
-        cur_op = op;
+        if cmd.assigns.is_empty() && cmd.args.is_empty() && cmd.redirects.is_empty() {
+            Err(self.unexpected())
+        } else {
+            Ok(Command::Simple(cmd))
+        }
     }
-    if cur_op.is_none() {
-        // Correct
-        Some(cur)
-    } else {
-        None
+}
+
+pub fn parse(toks: Vec<Token>) -> Result<List, ParseError> {
+    // Recursive descent over the POSIX shell grammar
+    // complete_command : list separator_op?
+    // list             : and_or (separator_op and_or)*
+    let mut parser = Parser { toks, pos: 0 };
+    let list = parser.compound_list()?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected());
+    }
+    Ok(list)
+}
+
+/// Whether the input needs more lines, like an open quote, a trailing `|` or an `if` without `fi`
+pub fn is_incomplete(line: &str) -> bool {
+    match lex(line) {
+        // Every lex error is an unfinished quote, here-document or line
+        Err(_) => true,
+        Ok(toks) => matches!(parse(toks), Err(ParseError::UnexpectedEof)),
     }
 }
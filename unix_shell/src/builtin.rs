@@ -0,0 +1,1961 @@
+use crate::ast::Command;
+use crate::complete::Spec;
+use crate::eval::{
+    execute, exit_status, quote, run_program, seconds, with_frame, Interrupt, Times,
+};
+use crate::job::Outcome;
+use crate::lex::{is_name, RESERVED};
+use crate::shell::{Options, Shell, Value, OPTIONS};
+use crate::trap::{self, Condition};
+use nix::errno::Errno;
+use nix::libc::STDIN_FILENO;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::resource::{getrlimit, rlim_t, setrlimit, Resource, RLIM_INFINITY};
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::sys::stat::{umask, Mode};
+use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg};
+use nix::unistd::{chdir, execve, getcwd, isatty, Pid};
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, Write};
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::str::Chars;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// A command run inside the shell, given its arguments without the name
+pub type Builtin = fn(&mut Shell, &[String]) -> Result<i32, Interrupt>;
+
+/// Every builtin by name, sorted
+pub const BUILTINS: &[(&str, Builtin)] = &[
+    (".", source),
+    (":", true_builtin),
+    ("builtin", builtin),
+    ("cd", cd),
+    ("command", command),
+    ("complete", complete),
+    ("dirs", dirs),
+    ("echo", echo),
+    ("eval", eval),
+    ("exec", exec),
+    ("exit", exit),
+    ("false", false_builtin),
+    ("hash", hash),
+    ("kill", kill_builtin),
+    ("popd", popd),
+    ("printf", printf),
+    ("pushd", pushd),
+    ("pwd", pwd),
+    ("read", read),
+    ("return", return_builtin),
+    ("set", set),
+    ("source", source),
+    ("times", times),
+    ("trap", trap_builtin),
+    ("true", true_builtin),
+    ("type", type_builtin),
+    ("ulimit", ulimit),
+    ("umask", umask_builtin),
+    ("wait", wait),
+];
+
+pub fn find(name: &str) -> Option<Builtin> {
+    BUILTINS
+        .binary_search_by_key(&name, |(n, _)| n)
+        .ok()
+        .map(|i| BUILTINS[i].1)
+}
+
+// An I/O error as the C library words it, without Rust's `(os error 2)`
+fn describe(e: &io::Error) -> String {
+    match e.raw_os_error() {
+        Some(code) => Errno::from_i32(code).desc().to_string(),
+        None => e.to_string(),
+    }
+}
+
+// Write to stdout, flushed at once since a redirection may point it elsewhere for now
+fn write_out(name: &str, bytes: &[u8]) -> Result<(), Interrupt> {
+    let mut out = io::stdout().lock();
+    out.write_all(bytes)
+        .and_then(|_| out.flush())
+        .map_err(|e| Interrupt::ExecError(format!("{}: write error: {}", name, describe(&e))))
+}
+
+fn true_builtin(_: &mut Shell, _: &[String]) -> Result<i32, Interrupt> {
+    Ok(0)
+}
+
+fn false_builtin(_: &mut Shell, _: &[String]) -> Result<i32, Interrupt> {
+    Ok(1)
+}
+
+fn exec(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // exec [-c] [--] [command [args]], the redirections are applied by `eval_simple`
+    let clear_env = args.first().is_some_and(|a| a == "-c");
+    let args = &args[clear_env as usize..];
+    let args = args.strip_prefix(&["--".to_string()]).unwrap_or(args);
+    let Some(name) = args.first() else {
+        return Ok(0);
+    };
+    let cargs: Vec<CString> = args
+        .iter()
+        .map(|a| CString::new(a.as_str()))
+        .collect::<Result<_, _>>()
+        .map_err(|_| Interrupt::ExecError(format!("exec: {}: argument contains NUL", name)))?;
+    let env = if clear_env { Vec::new() } else { shell.envp() };
+    let path = shell.find_command(name).unwrap_or_else(|| name.clone());
+    let path = CString::new(path)
+        .map_err(|_| Interrupt::ExecError(format!("exec: {}: path contains NUL", name)))?;
+    // Only comes back when it failed
+    let Err(e) = execve(&path, &cargs, &env);
+    let status = if e == Errno::ENOENT { 127 } else { 126 };
+    let message = format!("exec: {}: {}", name, e.desc());
+    if shell.interactive {
+        return Err(Interrupt::ExecError(message));
+    }
+    // A script cannot go on without the program that was to replace it
+    eprintln!("Error: {}", message);
+    Err(Interrupt::Exit(status))
+}
+
+fn exit(_: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    match args {
+        // Default exit 0
+        [] => Err(Interrupt::Exit(0)),
+        [code] => match code.parse() {
+            Ok(code) => Err(Interrupt::Exit(code)),
+            Err(_) => Err(Interrupt::ExecError(format!(
+                "exit: {}: numeric argument required",
+                code
+            ))),
+        },
+        _ => Err(Interrupt::ExecError("exit: too many arguments".into())),
+    }
+}
+
+// `path` without `.`, `..` with the directory before it, or repeated slashes
+fn canonical(path: &str) -> String {
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+// Change the working directory and keep PWD and OLDPWD up to date. With `physical`,
+// symlinks in `dir` are resolved, otherwise `..` goes back through them as typed
+pub(crate) fn change_dir(shell: &mut Shell, dir: &str, physical: bool) -> Result<(), Interrupt> {
+    let err = |e: Errno| Interrupt::ExecError(format!("cd: {}: {}", dir, e.desc()));
+    // When the working directory was removed, PWD still says where it was
+    let old = shell.cwd().ok().or_else(|| {
+        shell
+            .var("PWD")
+            .filter(|pwd| pwd.starts_with('/'))
+            .map(String::from)
+    });
+    let logical = if dir.starts_with('/') {
+        Some(canonical(dir))
+    } else {
+        old.as_ref()
+            .map(|old| canonical(&format!("{}/{}", old, dir)))
+    };
+    let physical_cd = || -> Result<String, Interrupt> {
+        chdir(dir).map_err(err)?;
+        Ok(getcwd().map_err(err)?.to_string_lossy().into_owned())
+    };
+    let pwd = match logical.filter(|_| !physical) {
+        // As typed, unless `..` cannot be taken that way
+        Some(logical) => match chdir(logical.as_str()) {
+            Ok(()) => logical,
+            Err(_) => physical_cd()?,
+        },
+        None => physical_cd()?,
+    };
+    if let Some(old) = old {
+        shell.set_var("OLDPWD", old);
+        shell.export("OLDPWD");
+    }
+    shell.set_var("PWD", pwd);
+    shell.export("PWD");
+    Ok(())
+}
+
+fn cd(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // cd [-L|-P] [dir|-]
+    let mut physical = false;
+    let mut args = args;
+    while let Some((arg, rest)) = args.split_first() {
+        match arg.as_str() {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            "--" => {
+                args = rest;
+                break;
+            }
+            _ => break,
+        }
+        args = rest;
+    }
+    let var = |name: &str| {
+        shell
+            .var(name)
+            .map(String::from)
+            .ok_or_else(|| Interrupt::ExecError(format!("cd: {} not set", name)))
+    };
+    let (dir, mut print) = match args {
+        [] => (var("HOME")?, false),
+        [dir] if dir == "-" => (var("OLDPWD")?, true),
+        [dir] => (dir.clone(), false),
+        _ => return Err(Interrupt::ExecError("cd: too many arguments".into())),
+    };
+    if dir.is_empty() {
+        return Ok(0);
+    }
+    // A relative name not starting with `.` or `..` is looked up in CDPATH first
+    let first = dir.split('/').next().unwrap_or_default();
+    let mut target = dir.clone();
+    if !dir.starts_with('/') && first != "." && first != ".." {
+        let cdpath = shell.var("CDPATH").unwrap_or_default();
+        for base in cdpath.split(':').filter(|_| !cdpath.is_empty()) {
+            let candidate = if base.is_empty() {
+                dir.clone()
+            } else {
+                format!("{}/{}", base.trim_end_matches('/'), dir)
+            };
+            if fs::metadata(&candidate).is_ok_and(|m| m.is_dir()) {
+                // The directory is shown when it is not the one typed
+                print |= !base.is_empty();
+                target = candidate;
+                break;
+            }
+        }
+    }
+    change_dir(shell, &target, physical)?;
+    if print {
+        let pwd = shell.var("PWD").unwrap_or_default();
+        write_out("cd", format!("{}\n", pwd).as_bytes())?;
+    }
+    Ok(0)
+}
+
+// The working directory followed by the `pushd` stack, as `dirs` shows it
+fn dir_stack(shell: &Shell) -> Vec<String> {
+    let cwd = shell.cwd().unwrap_or_else(|_| ".".into());
+    std::iter::once(cwd)
+        .chain(shell.dirs.iter().cloned())
+        .collect()
+}
+
+// The index in the stack for `+N` (from the left) or `-N` (from the right)
+fn stack_index(name: &str, arg: &str, len: usize) -> Result<Option<usize>, Interrupt> {
+    let (from_right, n) = match (arg.strip_prefix('+'), arg.strip_prefix('-')) {
+        (Some(n), _) => (false, n),
+        (_, Some(n)) => (true, n),
+        _ => return Ok(None),
+    };
+    let Ok(n) = n.parse::<usize>() else {
+        return Ok(None);
+    };
+    let index = if from_right {
+        len.checked_sub(n + 1)
+    } else {
+        Some(n).filter(|&n| n < len)
+    };
+    index.map(Some).ok_or_else(|| {
+        Interrupt::ExecError(format!(
+            "{}: {}: directory stack index out of range",
+            name, arg
+        ))
+    })
+}
+
+// `~` for the home directory, as in `dirs`
+fn abbreviate(shell: &Shell, dir: &str) -> String {
+    match shell.var("HOME").filter(|h| !h.is_empty() && *h != "/") {
+        Some(home) if dir == home => "~".into(),
+        Some(home) if dir.starts_with(&format!("{}/", home)) => format!("~{}", &dir[home.len()..]),
+        _ => dir.into(),
+    }
+}
+
+fn dirs(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // dirs [-clpv] [+N|-N]
+    let (mut clear, mut long, mut lines, mut numbered) = (false, false, false, false);
+    let stack = dir_stack(shell);
+    let mut only = None;
+    for arg in args {
+        if let Some(i) = stack_index("dirs", arg, stack.len())? {
+            only = Some(i);
+            continue;
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|f| !f.is_empty()) else {
+            return Err(Interrupt::ExecError(format!(
+                "dirs: {}: invalid argument",
+                arg
+            )));
+        };
+        for flag in flags.chars() {
+            match flag {
+                'c' => clear = true,
+                'l' => long = true,
+                'p' => lines = true,
+                'v' => (lines, numbered) = (true, true),
+                _ => {
+                    return Err(Interrupt::ExecError(format!(
+                        "dirs: -{}: invalid option",
+                        flag
+                    )))
+                }
+            }
+        }
+    }
+    if clear {
+        shell.dirs.clear();
+        return Ok(0);
+    }
+    let show = |dir: &String| {
+        if long {
+            dir.clone()
+        } else {
+            abbreviate(shell, dir)
+        }
+    };
+    let mut out = String::new();
+    for (i, dir) in stack.iter().enumerate() {
+        if only.is_some_and(|o| o != i) {
+            continue;
+        }
+        if numbered {
+            out += &format!("{:2}  {}\n", i, show(dir));
+        } else if lines || only.is_some() {
+            out += &format!("{}\n", show(dir));
+        } else {
+            out += &format!("{}{}", if i > 0 { " " } else { "" }, show(dir));
+        }
+    }
+    if !lines && only.is_none() {
+        out.push('\n');
+    }
+    write_out("dirs", out.as_bytes())?;
+    Ok(0)
+}
+
+// Make `stack` the new one, changing to its top if `cd`, and show it
+fn set_stack(
+    shell: &mut Shell,
+    name: &str,
+    mut stack: Vec<String>,
+    cd: bool,
+) -> Result<i32, Interrupt> {
+    let top = stack.remove(0);
+    if cd {
+        change_dir(shell, &top, false).map_err(|e| match e {
+            Interrupt::ExecError(e) => {
+                Interrupt::ExecError(format!("{}{}", name, e.trim_start_matches("cd")))
+            }
+            e => e,
+        })?;
+    }
+    shell.dirs = stack;
+    dirs(shell, &[])
+}
+
+fn pushd(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // pushd [-n] [dir|+N|-N]
+    let no_cd = args.first().is_some_and(|a| a == "-n");
+    let args = &args[no_cd as usize..];
+    let mut stack = dir_stack(shell);
+    match args {
+        [] if stack.len() < 2 => {
+            return Err(Interrupt::ExecError("pushd: no other directory".into()))
+        }
+        // Swap the top two
+        [] if no_cd => return Ok(0),
+        [] => stack.swap(0, 1),
+        [arg] => match stack_index("pushd", arg, stack.len())? {
+            // Rotate the Nth to the top, or with -n only the ones below it
+            Some(i) if no_cd => stack[1..].rotate_left(i.saturating_sub(1)),
+            Some(i) => stack.rotate_left(i),
+            None if no_cd => stack.insert(1, arg.clone()),
+            None => stack.insert(0, arg.clone()),
+        },
+        _ => return Err(Interrupt::ExecError("pushd: too many arguments".into())),
+    }
+    set_stack(shell, "pushd", stack, !no_cd)
+}
+
+fn popd(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // popd [-n] [+N|-N]
+    let no_cd = args.first().is_some_and(|a| a == "-n");
+    let args = &args[no_cd as usize..];
+    let mut stack = dir_stack(shell);
+    if stack.len() < 2 {
+        return Err(Interrupt::ExecError("popd: directory stack empty".into()));
+    }
+    let index = match args {
+        [] => 0,
+        [arg] => stack_index("popd", arg, stack.len())?
+            .ok_or_else(|| Interrupt::ExecError(format!("popd: {}: invalid argument", arg)))?,
+        _ => return Err(Interrupt::ExecError("popd: too many arguments".into())),
+    };
+    // With -n the working directory stays, and the one below it goes instead
+    let index = if no_cd { index.max(1) } else { index };
+    stack.remove(index);
+    set_stack(shell, "popd", stack, index == 0)
+}
+
+fn eval(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    execute(shell, &args.join(" "))
+}
+
+fn source(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // . file [arg ...]
+    let Some((file, rest)) = args.split_first() else {
+        return Err(Interrupt::ExecError(
+            "source: filename argument required".into(),
+        ));
+    };
+    // A name without a `/` is looked up in PATH, then in the working directory
+    let path = if file.contains('/') {
+        None
+    } else {
+        let path = shell.var("PATH").unwrap_or_default();
+        env::split_paths(path)
+            .map(|dir| dir.join(file))
+            .find(|p| p.is_file())
+    };
+    let path = path.unwrap_or_else(|| PathBuf::from(file));
+    let text = fs::read_to_string(&path)
+        .map_err(|e| Interrupt::ExecError(format!("source: {}: {}", file, describe(&e))))?;
+    // The arguments are the positional parameters while it runs, if there are any
+    let args = if rest.is_empty() { None } else { Some(rest) };
+    with_frame(shell, args, |sh| execute(sh, &text))
+}
+
+fn return_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // return [n]
+    if shell.frames == 0 {
+        return Err(Interrupt::ExecError(
+            "return: can only return from a function or sourced file".into(),
+        ));
+    }
+    let status = match args.first() {
+        None => shell.status,
+        Some(n) => n.parse::<i32>().map_err(|_| {
+            Interrupt::ExecError(format!("return: {}: numeric argument required", n))
+        })?,
+    };
+    Err(Interrupt::Return(status & 0xff))
+}
+
+fn pwd(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // pwd [-L|-P]
+    let mut physical = false;
+    for arg in args {
+        match arg.as_str() {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            _ => {
+                return Err(Interrupt::ExecError(format!(
+                    "pwd: {}: invalid option",
+                    arg
+                )))
+            }
+        }
+    }
+    let err = |e: Errno| Interrupt::ExecError(format!("pwd: {}", e.desc()));
+    let dir = if physical {
+        getcwd().map_err(err)?.to_string_lossy().into_owned()
+    } else {
+        shell.cwd().map_err(err)?
+    };
+    write_out("pwd", format!("{}\n", dir).as_bytes())?;
+    Ok(0)
+}
+
+// The escape after a `\`, as in `echo -e` and `printf`. With `zero_octal`, as in `echo -e`
+// and `%b`, octal escapes are `\0nnn` and `\c` ends all output (returning true). Otherwise
+// they are `\nnn`
+fn escape(it: &mut Peekable<Chars>, zero_octal: bool, out: &mut Vec<u8>) -> bool {
+    let digits = |it: &mut Peekable<Chars>, radix: u32, max: usize| {
+        let mut value = 0u32;
+        let mut n = 0;
+        while n < max {
+            let Some(d) = it.peek().and_then(|c| c.to_digit(radix)) else {
+                break;
+            };
+            value = value * radix + d;
+            it.next();
+            n += 1;
+        }
+        (value, n)
+    };
+    let Some(c) = it.next() else {
+        out.push(b'\\');
+        return false;
+    };
+    let byte = match c {
+        'a' => 7,
+        'b' => 8,
+        'e' => 0x1b,
+        'f' => 0xc,
+        'n' => b'\n',
+        'r' => b'\r',
+        't' => b'\t',
+        'v' => 0xb,
+        '\\' => b'\\',
+        'c' if zero_octal => return true,
+        '0' if zero_octal => digits(it, 8, 3).0 as u8,
+        '0'..='7' if !zero_octal => {
+            let first = c.to_digit(8).unwrap_or(0);
+            let (rest, n) = digits(it, 8, 2);
+            (first * 8u32.pow(n as u32) + rest) as u8
+        }
+        'x' => match digits(it, 16, 2) {
+            (_, 0) => {
+                out.extend_from_slice(b"\\x");
+                return false;
+            }
+            (value, _) => value as u8,
+        },
+        '"' if !zero_octal => b'"',
+        c => {
+            out.push(b'\\');
+            out.extend_from_slice(c.to_string().as_bytes());
+            return false;
+        }
+    };
+    out.push(byte);
+    false
+}
+
+// The text with its escapes decoded, and whether it had a `\c`
+fn unescape(s: &str, zero_octal: bool) -> (Vec<u8>, bool) {
+    let mut out = Vec::with_capacity(s.len());
+    let mut it = s.chars().peekable();
+    while let Some(c) = it.next() {
+        if c != '\\' {
+            out.extend_from_slice(c.to_string().as_bytes());
+        } else if escape(&mut it, zero_octal, &mut out) {
+            return (out, true);
+        }
+    }
+    (out, false)
+}
+
+fn echo(_: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // echo [-neE] [arg ...]
+    let (mut newline, mut escapes) = (true, false);
+    let mut words = args;
+    while let Some((arg, rest)) = words.split_first() {
+        let flags = arg.strip_prefix('-').unwrap_or_default();
+        if flags.is_empty() || !flags.chars().all(|c| "neE".contains(c)) {
+            break;
+        }
+        for c in flags.chars() {
+            match c {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        words = rest;
+    }
+    let mut out = Vec::new();
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            out.push(b' ');
+        }
+        if !escapes {
+            out.extend_from_slice(word.as_bytes());
+            continue;
+        }
+        let (bytes, stop) = unescape(word, true);
+        out.extend(bytes);
+        if stop {
+            newline = false;
+            break;
+        }
+    }
+    if newline {
+        out.push(b'\n');
+    }
+    write_out("echo", &out)?;
+    Ok(0)
+}
+
+// A printf directive: `%-08.3d` is flags `-0`, width 8 and precision 3
+#[derive(Default)]
+struct Directive {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Directive {
+    // Pad `body`, putting zeros between the sign or `0x` and the digits when asked to
+    fn pad(&self, prefix: &str, body: &str, numeric: bool) -> String {
+        let len = prefix.chars().count() + body.chars().count();
+        let fill = self.width.saturating_sub(len);
+        if self.left {
+            format!("{}{}{}", prefix, body, " ".repeat(fill))
+        } else if self.zero && numeric {
+            format!("{}{}{}", prefix, "0".repeat(fill), body)
+        } else {
+            format!("{}{}{}", " ".repeat(fill), prefix, body)
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    fn integer(&self, conv: char, value: i128) -> String {
+        let signed = matches!(conv, 'd' | 'i');
+        // Negative numbers wrap around for the unsigned conversions, as in C
+        let magnitude = if signed {
+            value.unsigned_abs()
+        } else {
+            value as u64 as u128
+        };
+        let mut digits = match conv {
+            'o' => format!("{:o}", magnitude),
+            'x' => format!("{:x}", magnitude),
+            'X' => format!("{:X}", magnitude),
+            _ => magnitude.to_string(),
+        };
+        if let Some(p) = self.precision {
+            if p == 0 && magnitude == 0 {
+                digits.clear();
+            } else if digits.len() < p {
+                digits = "0".repeat(p - digits.len()) + &digits;
+            }
+        }
+        let prefix = match conv {
+            _ if signed => self.sign(value < 0),
+            'o' if self.alt && !digits.starts_with('0') => "0",
+            'x' if self.alt && magnitude != 0 => "0x",
+            'X' if self.alt && magnitude != 0 => "0X",
+            _ => "",
+        };
+        self.pad(prefix, &digits, self.precision.is_none())
+    }
+
+    fn float(&self, conv: char, value: f64) -> String {
+        let upper = conv.is_ascii_uppercase();
+        let sign = self.sign(value.is_sign_negative() && !value.is_nan());
+        let value = value.abs();
+        if !value.is_finite() {
+            let body = if value.is_nan() { "nan" } else { "inf" };
+            let body = if upper {
+                body.to_uppercase()
+            } else {
+                body.into()
+            };
+            return self.pad(sign, &body, false);
+        }
+        let p = self.precision.unwrap_or(6);
+        let mut body = match conv.to_ascii_lowercase() {
+            'e' => exponent(value, p),
+            'g' => {
+                // The shorter of %e and %f, without trailing zeros
+                let p = p.max(1);
+                let x = if value == 0.0 {
+                    0
+                } else {
+                    let e = exponent(value, p - 1);
+                    e[e.find('e').unwrap_or(0) + 1..].parse().unwrap_or(0)
+                };
+                let mut body = if (p as i32) > x && x >= -4 {
+                    format!("{:.*}", (p as i32 - 1 - x) as usize, value)
+                } else {
+                    exponent(value, p - 1)
+                };
+                if !self.alt && body.contains('.') {
+                    let exp_at = body.find('e').unwrap_or(body.len());
+                    let mantissa = body[..exp_at].trim_end_matches('0').trim_end_matches('.');
+                    body = format!("{}{}", mantissa, &body[exp_at..]);
+                }
+                body
+            }
+            _ => format!("{:.*}", p, value),
+        };
+        if self.alt && !body.contains('.') {
+            let at = body.find('e').unwrap_or(body.len());
+            body.insert(at, '.');
+        }
+        if upper {
+            body = body.to_uppercase();
+        }
+        self.pad(sign, &body, true)
+    }
+}
+
+// `value` as `d.ddde+xx`, with `p` digits after the point
+fn exponent(value: f64, p: usize) -> String {
+    let s = format!("{:.*e}", p, value);
+    let (mantissa, exp) = s.split_once('e').unwrap_or((&s, "0"));
+    let exp: i32 = exp.parse().unwrap_or(0);
+    let sign = if exp < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exp.abs())
+}
+
+// The argument of a numeric conversion. A leading quote gives the code of the next character
+fn integer_arg(arg: &str) -> Result<i128, String> {
+    let s = arg.trim_start();
+    if let Some(rest) = s.strip_prefix(['\'', '"']) {
+        return Ok(rest.chars().next().map_or(0, |c| c as i128));
+    }
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (radix, digits) = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        (16, hex)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        (8, &digits[1..])
+    } else {
+        (10, digits)
+    };
+    let value = i128::from_str_radix(digits, radix)
+        .ok()
+        .filter(|_| !digits.starts_with(['+', '-']))
+        .ok_or_else(|| format!("{}: invalid number", arg))?;
+    Ok(if negative { -value } else { value })
+}
+
+fn float_arg(arg: &str) -> Result<f64, String> {
+    let s = arg.trim_start();
+    if s.starts_with(['\'', '"']) {
+        return integer_arg(s).map(|v| v as f64);
+    }
+    s.parse()
+        .or_else(|_| integer_arg(s).map(|v| v as f64))
+        .map_err(|_| format!("{}: invalid number", arg))
+}
+
+// A missing argument is 0, and an invalid one too but it makes the status 1
+fn number<T: Default>(
+    arg: Option<&String>,
+    parse: fn(&str) -> Result<T, String>,
+    status: &mut i32,
+) -> T {
+    let Some(arg) = arg else {
+        return T::default();
+    };
+    parse(arg).unwrap_or_else(|e| {
+        eprintln!("printf: {}", e);
+        *status = 1;
+        T::default()
+    })
+}
+
+// A width or precision, either digits or `*` for the next argument. Like in C, it has to
+// fit in an int
+fn count(
+    it: &mut Peekable<Chars>,
+    args: &mut std::slice::Iter<String>,
+    status: &mut i32,
+    what: &str,
+) -> Result<i128, Interrupt> {
+    let n = if it.next_if_eq(&'*').is_some() {
+        number(args.next(), integer_arg, status)
+    } else {
+        let mut n: i128 = 0;
+        while let Some(d) = it.peek().and_then(|c| c.to_digit(10)) {
+            n = n.saturating_mul(10).saturating_add(d as i128);
+            it.next();
+        }
+        n
+    };
+    if n.unsigned_abs() > i32::MAX as u128 {
+        return Err(Interrupt::ExecError(format!(
+            "printf: {}: invalid {}",
+            n, what
+        )));
+    }
+    Ok(n)
+}
+
+// Go through the format once, taking arguments from `args`. Returns true when `\c` ends
+// the output early
+fn format_once(
+    format: &str,
+    args: &mut std::slice::Iter<String>,
+    out: &mut Vec<u8>,
+    status: &mut i32,
+) -> Result<bool, Interrupt> {
+    let mut it = format.chars().peekable();
+    while let Some(c) = it.next() {
+        match c {
+            '\\' => {
+                if escape(&mut it, false, out) {
+                    return Ok(true);
+                }
+                continue;
+            }
+            '%' => {}
+            c => {
+                out.extend_from_slice(c.to_string().as_bytes());
+                continue;
+            }
+        }
+        let mut d = Directive::default();
+        while let Some(flag) = it.next_if(|c| "-+ #0".contains(*c)) {
+            match flag {
+                '-' => d.left = true,
+                '+' => d.plus = true,
+                ' ' => d.space = true,
+                '#' => d.alt = true,
+                _ => d.zero = true,
+            }
+        }
+        let width = count(&mut it, args, status, "field width")?;
+        // A negative width from `*` means left-justified
+        d.left |= width < 0;
+        d.width = width.unsigned_abs() as usize;
+        if it.next_if_eq(&'.').is_some() {
+            // A negative precision is as if there was none
+            d.precision = usize::try_from(count(&mut it, args, status, "precision")?).ok();
+        }
+        let conv = it.next();
+        let arg = match conv {
+            Some('%') => {
+                out.push(b'%');
+                continue;
+            }
+            Some(_) => args.next(),
+            None => None,
+        };
+        let text = match conv {
+            Some(conv @ ('d' | 'i' | 'o' | 'u' | 'x' | 'X')) => {
+                d.integer(conv, number(arg, integer_arg, status))
+            }
+            Some(conv @ ('f' | 'F' | 'e' | 'E' | 'g' | 'G')) => {
+                d.float(conv, number(arg, float_arg, status))
+            }
+            Some('c') => {
+                let c = arg.and_then(|a| a.chars().next());
+                d.pad("", &c.map(String::from).unwrap_or_default(), false)
+            }
+            Some('s') => {
+                let s = arg.map_or("", String::as_str);
+                let s: String = match d.precision {
+                    Some(p) => s.chars().take(p).collect(),
+                    None => s.into(),
+                };
+                d.pad("", &s, false)
+            }
+            Some('b') => {
+                let (bytes, stop) = unescape(arg.map_or("", String::as_str), true);
+                let fill = " ".repeat(d.width.saturating_sub(bytes.len()));
+                if !d.left {
+                    out.extend_from_slice(fill.as_bytes());
+                }
+                out.extend(bytes);
+                if stop {
+                    return Ok(true);
+                }
+                if d.left {
+                    out.extend_from_slice(fill.as_bytes());
+                }
+                continue;
+            }
+            Some(c) => {
+                return Err(Interrupt::ExecError(format!(
+                    "printf: %{}: invalid directive",
+                    c
+                )))
+            }
+            None => {
+                return Err(Interrupt::ExecError(
+                    "printf: %: missing format character".into(),
+                ))
+            }
+        };
+        out.extend_from_slice(text.as_bytes());
+    }
+    Ok(false)
+}
+
+fn printf(_: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // printf format [arg ...]
+    let Some((format, args)) = args.split_first() else {
+        return Err(Interrupt::ExecError(
+            "printf: usage: printf format [arguments]".into(),
+        ));
+    };
+    let mut out = Vec::new();
+    let mut status = 0;
+    let mut it = args.iter();
+    let res = loop {
+        let left = it.len();
+        match format_once(format, &mut it, &mut out, &mut status) {
+            // The format is used again for the arguments that are left
+            Ok(false) if it.len() > 0 && it.len() < left => continue,
+            res => break res,
+        }
+    };
+    // What was formatted before an error is still printed
+    write_out("printf", &out)?;
+    res?;
+    Ok(status)
+}
+
+// One byte of stdin. Reading more could take input meant for the next command
+// `Ok(None)` is the end of the input, and `ETIMEDOUT` the deadline passing first
+fn read_byte(deadline: Option<Instant>) -> nix::Result<Option<u8>> {
+    if let Some(deadline) = deadline {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let stdin = io::stdin();
+        let mut fds = [PollFd::new(&stdin, PollFlags::POLLIN)];
+        if poll(&mut fds, left.as_millis().try_into().unwrap_or(i32::MAX))? == 0 {
+            return Err(Errno::ETIMEDOUT);
+        }
+    }
+    let mut buf = [0];
+    loop {
+        match nix::unistd::read(STDIN_FILENO, &mut buf) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(buf[0])),
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Split a line into at most `n` fields on `IFS`, the last one keeping the rest of the line
+// Bytes escaped with `\` are never separators
+fn split_fields(line: &[(u8, bool)], ifs: &[u8], n: usize) -> Vec<String> {
+    let is_ifs = |&(b, escaped): &(u8, bool)| !escaped && ifs.contains(&b);
+    let is_blank = |p: &(u8, bool)| is_ifs(p) && b" \t\n".contains(&p.0);
+    let text = |part: &[(u8, bool)]| {
+        let bytes: Vec<u8> = part.iter().map(|&(b, _)| b).collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    let mut start = 0;
+    let mut end = line.len();
+    while start < end && is_blank(&line[start]) {
+        start += 1;
+    }
+    while end > start && is_blank(&line[end - 1]) {
+        end -= 1;
+    }
+    let mut fields = Vec::new();
+    let mut i = start;
+    while i < end {
+        if fields.len() + 1 == n {
+            fields.push(text(&line[i..end]));
+            break;
+        }
+        let field = i;
+        while i < end && !is_ifs(&line[i]) {
+            i += 1;
+        }
+        fields.push(text(&line[field..i]));
+        // A separator is blanks, with at most one other IFS character among them
+        while i < end && is_blank(&line[i]) {
+            i += 1;
+        }
+        if i < end && is_ifs(&line[i]) && !is_blank(&line[i]) {
+            i += 1;
+            while i < end && is_blank(&line[i]) {
+                i += 1;
+            }
+        }
+    }
+    fields
+}
+
+fn read(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // read [-rs] [-a array] [-d delim] [-n count] [-p prompt] [-t timeout] [name ...]
+    let (mut raw, mut silent) = (false, false);
+    let (mut array, mut prompt) = (None, None);
+    let (mut delim, mut count, mut timeout) = (b'\n', None, None);
+    let invalid = |msg: String| Interrupt::ExecError(format!("read: {}", msg));
+    let mut it = args.iter().peekable();
+    while let Some(arg) = it.next_if(|a| a.starts_with('-') && a.len() > 1) {
+        if arg == "--" {
+            break;
+        }
+        for (i, opt) in arg.char_indices().skip(1) {
+            if !"adnpt".contains(opt) {
+                match opt {
+                    'r' => raw = true,
+                    's' => silent = true,
+                    _ => return Err(invalid(format!("-{}: invalid option", opt))),
+                }
+                continue;
+            }
+            // The value is the rest of the word, or the next one
+            let value = match &arg[i + 1..] {
+                "" => it
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| invalid(format!("-{}: option requires an argument", opt)))?,
+                rest => rest.to_string(),
+            };
+            match opt {
+                'a' => array = Some(value),
+                // An empty delimiter is NUL
+                'd' => delim = value.bytes().next().unwrap_or(0),
+                'n' => {
+                    let n = value.parse::<usize>();
+                    count = Some(n.map_err(|_| invalid(format!("{}: invalid count", value)))?)
+                }
+                'p' => prompt = Some(value),
+                _ => {
+                    let secs = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|s| *s >= 0.0 && s.is_finite());
+                    let secs =
+                        secs.ok_or_else(|| invalid(format!("{}: invalid timeout", value)))?;
+                    timeout = Some(Duration::from_secs_f64(secs));
+                }
+            }
+            break;
+        }
+    }
+    let names: Vec<&String> = it.collect();
+    if let Some(name) = names
+        .iter()
+        .chain(array.as_ref().iter())
+        .find(|n| !is_name(n))
+    {
+        return Err(invalid(format!("{}: not a valid identifier", name)));
+    }
+
+    let tty = isatty(STDIN_FILENO).unwrap_or(false);
+    if let (Some(prompt), true) = (&prompt, tty) {
+        eprint!("{}", prompt);
+    }
+    if timeout == Some(Duration::ZERO) {
+        // Only whether there is input to read
+        let stdin = io::stdin();
+        let mut fds = [PollFd::new(&stdin, PollFlags::POLLIN)];
+        return Ok((poll(&mut fds, 0).unwrap_or(0) == 0) as i32);
+    }
+    let deadline = timeout.map(|t| Instant::now() + t);
+    // Turn off the echo of the terminal for -s, until the line is read
+    let saved = if silent && tty {
+        tcgetattr(io::stdin()).ok()
+    } else {
+        None
+    };
+    if let Some(mut termios) = saved.clone() {
+        termios.local_flags.remove(LocalFlags::ECHO);
+        let _ = tcsetattr(io::stdin(), SetArg::TCSANOW, &termios);
+    }
+
+    let mut line: Vec<(u8, bool)> = Vec::new(); // Bytes, and whether they were escaped
+    let mut chars = 0;
+    let res = loop {
+        if count.is_some_and(|n| chars >= n) {
+            break Ok(0);
+        }
+        let mut byte = match read_byte(deadline) {
+            Ok(Some(b)) => b,
+            Ok(None) => break Ok(1),
+            Err(e) => break Err(e),
+        };
+        if byte == delim {
+            break Ok(0);
+        }
+        let mut escaped = false;
+        if byte == b'\\' && !raw {
+            byte = match read_byte(deadline) {
+                // A line continuation
+                Ok(Some(b'\n')) => continue,
+                Ok(Some(b)) => b,
+                Ok(None) => break Ok(1),
+                Err(e) => break Err(e),
+            };
+            escaped = true;
+        }
+        line.push((byte, escaped));
+        // The rest of a UTF-8 character, so that -n counts characters
+        for _ in 1..byte.leading_ones() {
+            match read_byte(deadline) {
+                Ok(Some(b)) => line.push((b, escaped)),
+                _ => break,
+            }
+        }
+        chars += 1;
+    };
+    if let Some(termios) = saved {
+        let _ = tcsetattr(io::stdin(), SetArg::TCSANOW, &termios);
+    }
+    let status = match res {
+        Ok(status) => status,
+        // What was read before the timeout is still assigned
+        Err(Errno::ETIMEDOUT) => 128 + 14,
+        Err(e) => return Err(invalid(e.desc().to_string())),
+    };
+
+    let ifs = shell.var("IFS").unwrap_or(" \t\n").as_bytes().to_vec();
+    if let Some(array) = array {
+        let fields = split_fields(&line, &ifs, usize::MAX);
+        shell.set_array(&array, fields);
+    } else if names.is_empty() {
+        // REPLY is the whole line, blanks included
+        shell.set_var("REPLY", split_fields(&line, &[], 1).concat());
+    } else {
+        let mut fields = split_fields(&line, &ifs, names.len()).into_iter();
+        for name in names {
+            shell.set_var(name, fields.next().unwrap_or_default());
+        }
+    }
+    Ok(status)
+}
+
+fn complete(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // complete [-pr] [-W words] [-F function] [-C command] [name ...]
+    let mut spec = Spec::default();
+    let (mut print, mut remove) = (false, false);
+    let mut names = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = || {
+            it.next().cloned().ok_or_else(|| {
+                Interrupt::ExecError(format!("complete: {}: option requires an argument", arg))
+            })
+        };
+        match arg.as_str() {
+            "-W" => spec.words = value()?.split_whitespace().map(String::from).collect(),
+            "-F" => spec.function = Some(value()?),
+            "-C" => spec.command = Some(value()?),
+            "-p" => print = true,
+            "-r" => remove = true,
+            _ => names.push(arg.clone()),
+        }
+    }
+    if remove {
+        if names.is_empty() {
+            shell.completions.clear();
+        }
+        for name in &names {
+            shell.completions.remove(name);
+        }
+        return Ok(0);
+    }
+    if print || names.is_empty() {
+        let mut specs: Vec<_> = shell
+            .completions
+            .iter()
+            .filter(|(name, _)| names.is_empty() || names.contains(name))
+            .collect();
+        specs.sort_by_key(|(name, _)| *name);
+        let mut out = String::new();
+        for (name, spec) in specs {
+            out += "complete";
+            if !spec.words.is_empty() {
+                out += &format!(" -W {}", quote(&spec.words.join(" ")));
+            }
+            if let Some(f) = &spec.function {
+                out += &format!(" -F {}", f);
+            }
+            if let Some(c) = &spec.command {
+                out += &format!(" -C {}", quote(c));
+            }
+            out += &format!(" {}\n", name);
+        }
+        write_out("complete", out.as_bytes())?;
+        return Ok(0);
+    }
+    for name in names {
+        shell.completions.insert(name, spec.clone());
+    }
+    Ok(0)
+}
+
+fn wait_error(e: Errno) -> Interrupt {
+    Interrupt::ExecError(format!("wait: {}", e.desc()))
+}
+
+fn wait(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // wait [pid|%job ...]
+    if args.is_empty() {
+        // Every job, and the status is 0
+        for job in shell.jobs.iter_mut() {
+            job.wait().map_err(wait_error)?;
+        }
+        return Ok(0);
+    }
+    let mut status = 0;
+    for arg in args {
+        let job = if arg.starts_with('%') {
+            shell.jobs.find(arg)
+        } else {
+            let pid = arg.parse().map_err(|_| {
+                Interrupt::ExecError(format!("wait: {}: not a pid or valid job spec", arg))
+            })?;
+            shell.jobs.iter_mut().find(|j| j.pid == Pid::from_raw(pid))
+        };
+        let Some(job) = job else {
+            eprintln!("wait: {}: no such job", arg);
+            status = 127;
+            continue;
+        };
+        // A job reaped before the prompt keeps its status until it is reported
+        status = match job.wait().map_err(wait_error)? {
+            Outcome::Status(done) => exit_status(done).map_or(0, |(_, code)| code),
+            Outcome::Unknown => 127,
+        };
+    }
+    Ok(status)
+}
+
+// A signal by name (`TERM`, `SIGTERM`, any case) or number. 0 is no signal, which only
+// checks that the process exists
+fn signal_arg(spec: &str) -> Option<Option<Signal>> {
+    if let Ok(n) = spec.parse::<i32>() {
+        return if n == 0 {
+            Some(None)
+        } else {
+            Signal::try_from(n).ok().map(Some)
+        };
+    }
+    let name = spec.to_ascii_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    Signal::from_str(&name).ok().map(Some)
+}
+
+// `kill -l` shows every signal, or translates names to numbers and back, where a
+// number may also be an exit status like 143
+fn list_signals(args: &[String]) -> Result<i32, Interrupt> {
+    let mut out = String::new();
+    if args.is_empty() {
+        let all: Vec<String> = Signal::iterator()
+            .map(|sig| format!("{:2}) {:<8}", sig as i32, sig.as_str()))
+            .collect();
+        for row in all.chunks(5) {
+            out += row.join("\t").trim_end();
+            out.push('\n');
+        }
+    }
+    for arg in args {
+        let sig = match arg.parse::<i32>() {
+            Ok(n) => Signal::try_from(if n > 128 { n - 128 } else { n })
+                .map(|sig| sig.as_str().trim_start_matches("SIG").to_string()),
+            Err(_) => match signal_arg(arg) {
+                Some(Some(sig)) => Ok((sig as i32).to_string()),
+                _ => Err(Errno::EINVAL),
+            },
+        };
+        match sig {
+            Ok(sig) => out += &format!("{}\n", sig),
+            Err(_) => {
+                return Err(Interrupt::ExecError(format!(
+                    "kill: {}: invalid signal specification",
+                    arg
+                )))
+            }
+        }
+    }
+    write_out("kill", out.as_bytes())?;
+    Ok(0)
+}
+
+fn kill_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // kill [-s sig | -n num | -sig] pid|%job ..., kill -l [sig ...]
+    let invalid =
+        |spec: &str| Interrupt::ExecError(format!("kill: {}: invalid signal specification", spec));
+    let mut sig = Some(Signal::SIGTERM);
+    let mut args = args;
+    match args.first().map(String::as_str) {
+        Some("-l" | "-L") => return list_signals(&args[1..]),
+        Some("-s" | "-n") => {
+            let spec = args.get(1).ok_or_else(|| {
+                Interrupt::ExecError(format!("kill: {}: option requires an argument", args[0]))
+            })?;
+            sig = signal_arg(spec).ok_or_else(|| invalid(spec))?;
+            args = &args[2..];
+        }
+        Some("--") => {}
+        Some(flag) if flag.len() > 1 && flag.starts_with('-') => {
+            sig = signal_arg(&flag[1..]).ok_or_else(|| invalid(&flag[1..]))?;
+            args = &args[1..];
+        }
+        _ => {}
+    }
+    if args.first().is_some_and(|a| a == "--") {
+        args = &args[1..];
+    }
+    if args.is_empty() {
+        return Err(Interrupt::ExecError(
+            "kill: usage: kill [-s sig | -n num | -sig] pid|%job ... or kill -l [sig]".into(),
+        ));
+    }
+    let mut status = 0;
+    for arg in args {
+        let sent = if arg.starts_with('%') {
+            match shell.jobs.find(arg) {
+                Some(job) => killpg(job.pid, sig),
+                None => {
+                    eprintln!("kill: {}: no such job", arg);
+                    status = 1;
+                    continue;
+                }
+            }
+        } else {
+            // A negative pid is a process group
+            match arg.parse() {
+                Ok(pid) => kill(Pid::from_raw(pid), sig),
+                Err(_) => {
+                    eprintln!("kill: {}: arguments must be process or job IDs", arg);
+                    status = 1;
+                    continue;
+                }
+            }
+        };
+        if let Err(e) = sent {
+            eprintln!("kill: {}: {}", arg, e.desc());
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+fn times(_: &mut Shell, _: &[String]) -> Result<i32, Interrupt> {
+    // The user and system time of the shell, then of its children
+    let times = Times::now();
+    let mut out = String::new();
+    for (user, sys) in times.user.iter().zip(times.sys) {
+        out += &format!("{} {}\n", seconds(*user, 3, true), seconds(sys, 3, true));
+    }
+    write_out("times", out.as_bytes())?;
+    Ok(0)
+}
+
+fn trap_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // trap [-lp] [[action] condition ...]
+    let invalid = |spec: &str| {
+        eprintln!("trap: {}: invalid signal specification", spec);
+    };
+    let args = match args.first().map(String::as_str) {
+        Some("-l") => return list_signals(&[]),
+        Some("--") => &args[1..],
+        _ => args,
+    };
+    // Show them as the commands that would set them again
+    if args.is_empty() || args[0] == "-p" {
+        let mut status = 0;
+        let mut out = String::new();
+        let conds: Vec<Condition> = match args.get(1..).filter(|a| !a.is_empty()) {
+            Some(specs) => specs
+                .iter()
+                .filter_map(|spec| {
+                    let cond = Condition::parse(spec);
+                    if cond.is_none() {
+                        invalid(spec);
+                        status = 1;
+                    }
+                    cond
+                })
+                .collect(),
+            None => shell.traps.keys().copied().collect(),
+        };
+        for cond in conds {
+            if let Some(action) = shell.traps.get(&cond) {
+                out += &format!("trap -- {} {}\n", quote(action), cond.name());
+            }
+        }
+        write_out("trap", out.as_bytes())?;
+        return Ok(status);
+    }
+    // A lone condition, or `-` for the action, resets to the default
+    let (action, specs) = match args {
+        [spec] => (None, std::slice::from_ref(spec)),
+        [action, specs @ ..] if action == "-" => (None, specs),
+        [action, specs @ ..] => (Some(action.as_str()), specs),
+        [] => unreachable!(),
+    };
+    let mut status = 0;
+    for spec in specs {
+        let Some(cond) = Condition::parse(spec) else {
+            invalid(spec);
+            status = 1;
+            continue;
+        };
+        if let Condition::Signal(sig) = cond {
+            if let Err(e) = trap::set_handler(sig, action) {
+                eprintln!("trap: {}: {}", spec, e.desc());
+                status = 1;
+                continue;
+            }
+        }
+        match action {
+            Some(action) => shell.traps.insert(cond, action.to_string()),
+            None => shell.traps.remove(&cond),
+        };
+    }
+    Ok(status)
+}
+
+// The limits of `ulimit`: its letter, what `-a` calls it, the unit and its size in bytes
+const LIMITS: &[(char, &str, &str, rlim_t, Resource)] = &[
+    (
+        'c',
+        "core file size",
+        "blocks, ",
+        1024,
+        Resource::RLIMIT_CORE,
+    ),
+    ('f', "file size", "blocks, ", 1024, Resource::RLIMIT_FSIZE),
+    ('n', "open files", "", 1, Resource::RLIMIT_NOFILE),
+    ('s', "stack size", "kbytes, ", 1024, Resource::RLIMIT_STACK),
+    ('t', "cpu time", "seconds, ", 1, Resource::RLIMIT_CPU),
+    ('v', "virtual memory", "kbytes, ", 1024, Resource::RLIMIT_AS),
+];
+
+fn ulimit(_: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // ulimit [-SHa] [-cfnstv] [limit]
+    let (mut soft, mut hard, mut all) = (false, false, false);
+    let mut letter = 'f';
+    let mut value = None;
+    for arg in args {
+        let Some(flags) = arg.strip_prefix('-').filter(|f| !f.is_empty()) else {
+            value = Some(arg);
+            continue;
+        };
+        for flag in flags.chars() {
+            match flag {
+                'S' => soft = true,
+                'H' => hard = true,
+                'a' => all = true,
+                _ if LIMITS.iter().any(|l| l.0 == flag) => letter = flag,
+                _ => {
+                    return Err(Interrupt::ExecError(format!(
+                        "ulimit: -{}: invalid option",
+                        flag
+                    )))
+                }
+            }
+        }
+    }
+    let get = |resource| {
+        getrlimit(resource).map_err(|e| Interrupt::ExecError(format!("ulimit: {}", e.desc())))
+    };
+    // Shown from the soft limit unless only -H is given
+    let show = |(limit, unit): (rlim_t, rlim_t)| {
+        if limit == RLIM_INFINITY {
+            "unlimited".to_string()
+        } else {
+            (limit / unit).to_string()
+        }
+    };
+    let pick = |(cur, max): (rlim_t, rlim_t)| if hard && !soft { max } else { cur };
+    if all {
+        let mut out = String::new();
+        for (letter, name, unit, size, resource) in LIMITS {
+            let paren = format!("({}-{})", unit, letter);
+            let limit = show((pick(get(*resource)?), *size));
+            out += &format!("{:<w$}{} {}\n", name, paren, limit, w = 40 - paren.len());
+        }
+        write_out("ulimit", out.as_bytes())?;
+        return Ok(0);
+    }
+    let &(_, name, _, size, resource) = LIMITS.iter().find(|l| l.0 == letter).unwrap();
+    let (cur, max) = get(resource)?;
+    let Some(value) = value else {
+        write_out(
+            "ulimit",
+            format!("{}\n", show((pick((cur, max)), size))).as_bytes(),
+        )?;
+        return Ok(0);
+    };
+    let limit = match value.as_str() {
+        "unlimited" => RLIM_INFINITY,
+        "soft" => cur,
+        "hard" => max,
+        n => n
+            .parse::<rlim_t>()
+            .ok()
+            .and_then(|n| n.checked_mul(size))
+            .ok_or_else(|| Interrupt::ExecError(format!("ulimit: {}: invalid number", n)))?,
+    };
+    // Both limits are set unless one is asked for
+    let both = !soft && !hard;
+    let cur = if soft || both { limit } else { cur };
+    let max = if hard || both { limit } else { max };
+    setrlimit(resource, cur, max).map_err(|e| {
+        Interrupt::ExecError(format!(
+            "ulimit: {}: cannot modify limit: {}",
+            name,
+            e.desc()
+        ))
+    })?;
+    Ok(0)
+}
+
+// Apply a symbolic mode like `u=rwx,g+r,o-w` to the permissions `allowed`
+fn symbolic_mode(allowed: u32, spec: &str) -> Option<u32> {
+    let mut allowed = allowed;
+    for clause in spec.split(',') {
+        let ops = clause.trim_start_matches(['u', 'g', 'o', 'a']);
+        let who = clause[..clause.len() - ops.len()]
+            .chars()
+            .fold(0, |who, c| {
+                who | match c {
+                    'u' => 0o700,
+                    'g' => 0o070,
+                    'o' => 0o007,
+                    _ => 0o777,
+                }
+            });
+        let who = if who == 0 { 0o777 } else { who };
+        let mut ops = ops.chars().peekable();
+        ops.peek()?;
+        while let Some(op) = ops.next() {
+            let mut perms = 0;
+            while let Some(&c) = ops.peek() {
+                perms |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    _ => break,
+                };
+                ops.next();
+            }
+            let perms = perms & who;
+            allowed = match op {
+                '=' => (allowed & !who) | perms,
+                '+' => allowed | perms,
+                '-' => allowed & !perms,
+                _ => return None,
+            };
+        }
+    }
+    Some(allowed)
+}
+
+fn umask_builtin(_: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // umask [-pS] [mode]
+    let (mut symbolic, mut reusable) = (false, false);
+    let mut args = args;
+    while let Some(flags) = args.first().and_then(|a| a.strip_prefix('-')) {
+        if flags.is_empty() || !flags.chars().all(|c| c == 'p' || c == 'S') {
+            break;
+        }
+        symbolic |= flags.contains('S');
+        reusable |= flags.contains('p');
+        args = &args[1..];
+    }
+    // Reading the mask means setting it, so put it back at once
+    let old = umask(Mode::empty());
+    umask(old);
+    let old = old.bits() as u32;
+    let Some(mode) = args.first() else {
+        let text = if symbolic {
+            let allowed = !old & 0o777;
+            let perms = |shift: u32| {
+                let bits = allowed >> shift;
+                ["r", "w", "x"]
+                    .iter()
+                    .zip([4, 2, 1])
+                    .filter(|(_, bit)| bits & bit != 0)
+                    .map(|(p, _)| *p)
+                    .collect::<String>()
+            };
+            format!("u={},g={},o={}", perms(6), perms(3), perms(0))
+        } else {
+            format!("{:04o}", old)
+        };
+        let prefix = if reusable { "umask " } else { "" };
+        let flag = if reusable && symbolic { "-S " } else { "" };
+        write_out("umask", format!("{}{}{}\n", prefix, flag, text).as_bytes())?;
+        return Ok(0);
+    };
+    let mask = if mode.starts_with(|c: char| c.is_ascii_digit()) {
+        u32::from_str_radix(mode, 8).ok().filter(|m| *m <= 0o777)
+    } else {
+        symbolic_mode(!old & 0o777, mode).map(|allowed| !allowed & 0o777)
+    };
+    let mask =
+        mask.ok_or_else(|| Interrupt::ExecError(format!("umask: {}: invalid mode", mode)))?;
+    umask(Mode::from_bits_truncate(mask as _));
+    Ok(0)
+}
+
+// What a command name can be, in the order the shell looks for it
+enum Kind {
+    Keyword,
+    Function(Rc<Command>),
+    Builtin,
+    File(String, bool), // Whether it came from the hash table
+}
+
+// Everything `name` could run, first what it does run. Only the first file in `PATH`, as
+// hashed, unless `all`
+fn kinds(shell: &Shell, name: &str, all: bool) -> Vec<Kind> {
+    let mut kinds = Vec::new();
+    if RESERVED.contains(&name) {
+        kinds.push(Kind::Keyword);
+    }
+    if let Some(body) = shell.functions.get(name) {
+        kinds.push(Kind::Function(body.clone()));
+    }
+    if find(name).is_some() {
+        kinds.push(Kind::Builtin);
+    }
+    if name.contains('/') {
+        if crate::highlight::is_executable(std::path::Path::new(name)) {
+            kinds.push(Kind::File(name.to_string(), false));
+        }
+    } else if let Some((path, _)) = shell.hashed.get(name).filter(|_| !all) {
+        kinds.push(Kind::File(path.clone(), true));
+    } else {
+        let paths = shell.search_path(name).into_iter();
+        let paths = paths.take(if all { usize::MAX } else { 1 });
+        kinds.extend(paths.map(|path| Kind::File(path, false)));
+    }
+    kinds
+}
+
+// How `type` explains one of the kinds of `name`
+fn explain(name: &str, kind: &Kind) -> String {
+    match kind {
+        Kind::Keyword => format!("{} is a shell keyword", name),
+        Kind::Function(body) => format!("{} is a function\n{}() {}", name, name, body),
+        Kind::Builtin => format!("{} is a shell builtin", name),
+        Kind::File(path, true) => format!("{} is hashed ({})", name, path),
+        Kind::File(path, false) => format!("{} is {}", name, path),
+    }
+}
+
+fn type_builtin(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // type [-afptP] name ...
+    let (mut all, mut no_functions, mut word, mut path_only, mut force_path) =
+        (false, false, false, false, false);
+    let mut names = args;
+    while let Some(flags) = names.first().and_then(|a| a.strip_prefix('-')) {
+        names = &names[1..];
+        if flags == "-" {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'a' => all = true,
+                'f' => no_functions = true,
+                'p' => path_only = true,
+                't' => word = true,
+                'P' => force_path = true,
+                _ => {
+                    return Err(Interrupt::ExecError(format!(
+                        "type: -{}: invalid option",
+                        flag
+                    )))
+                }
+            }
+        }
+    }
+    let mut status = 0;
+    let mut out = String::new();
+    for name in names {
+        // Everything it could be, less what the flags leave out
+        let found: Vec<Kind> = kinds(shell, name, all)
+            .into_iter()
+            .filter(|k| match k {
+                Kind::File(..) => true,
+                Kind::Function(_) => !no_functions && !force_path,
+                _ => !force_path,
+            })
+            .take(if all { usize::MAX } else { 1 })
+            .collect();
+        if found.is_empty() {
+            if !word && !path_only {
+                eprintln!("type: {}: not found", name);
+            }
+            status = 1;
+            continue;
+        }
+        for kind in &found {
+            let line = match kind {
+                Kind::File(path, _) if path_only || force_path => path.clone(),
+                _ if path_only => continue,
+                _ if word => match kind {
+                    Kind::Keyword => "keyword",
+                    Kind::Function(_) => "function",
+                    Kind::Builtin => "builtin",
+                    Kind::File(..) => "file",
+                }
+                .to_string(),
+                _ => explain(name, kind),
+            };
+            out += &line;
+            out.push('\n');
+        }
+    }
+    write_out("type", out.as_bytes())?;
+    Ok(status)
+}
+
+fn command(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // command [-pvV] name [args], without functions
+    let (mut verbose, mut short, mut default_path) = (false, false, false);
+    let mut args = args;
+    while let Some(flags) = args.first().and_then(|a| a.strip_prefix('-')) {
+        args = &args[1..];
+        if flags == "-" {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'p' => default_path = true,
+                'v' => short = true,
+                'V' => verbose = true,
+                _ => {
+                    return Err(Interrupt::ExecError(format!(
+                        "command: -{}: invalid option",
+                        flag
+                    )))
+                }
+            }
+        }
+    }
+    let Some(name) = args.first() else {
+        return Ok(0);
+    };
+    if verbose || short {
+        let mut status = 0;
+        let mut out = String::new();
+        for name in args {
+            let Some(kind) = kinds(shell, name, false).into_iter().next() else {
+                if verbose {
+                    eprintln!("command: {}: not found", name);
+                }
+                status = 1;
+                continue;
+            };
+            out += &match kind {
+                _ if verbose => explain(name, &kind),
+                Kind::File(path, _) => path,
+                _ => name.clone(),
+            };
+            out.push('\n');
+        }
+        write_out("command", out.as_bytes())?;
+        return Ok(status);
+    }
+    if let Some(run) = find(name) {
+        return run(shell, &args[1..]);
+    }
+    if default_path {
+        // A PATH that finds the standard utilities, whatever the user's is
+        let saved = shell.var("PATH").map(str::to_string);
+        shell.set_var("PATH", "/usr/bin:/bin".into());
+        let res = run_program(shell, &[], args, false);
+        match saved {
+            Some(path) => shell.set_var("PATH", path),
+            None => shell.unset_var("PATH"),
+        }
+        return res;
+    }
+    run_program(shell, &[], args, false)
+}
+
+fn builtin(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // builtin name [args], even when a function has the same name
+    let Some(name) = args.first() else {
+        return Ok(0);
+    };
+    let run = find(name)
+        .ok_or_else(|| Interrupt::ExecError(format!("builtin: {}: not a shell builtin", name)))?;
+    run(shell, &args[1..])
+}
+
+fn hash(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // hash [-lrt] [-d name] [-p path name] [name ...]
+    let (mut clear, mut list, mut print, mut delete) = (false, false, false, false);
+    let mut path = None;
+    let mut args = args;
+    while let Some(flags) = args.first().and_then(|a| a.strip_prefix('-')) {
+        args = &args[1..];
+        if flags == "-" {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'r' => clear = true,
+                'l' => list = true,
+                't' => print = true,
+                'd' => delete = true,
+                'p' => {
+                    let (first, rest) = args.split_first().ok_or_else(|| {
+                        Interrupt::ExecError("hash: -p: option requires an argument".into())
+                    })?;
+                    path = Some(first.clone());
+                    args = rest;
+                }
+                _ => {
+                    return Err(Interrupt::ExecError(format!(
+                        "hash: -{}: invalid option",
+                        flag
+                    )))
+                }
+            }
+        }
+    }
+    if clear {
+        shell.hashed.clear();
+    }
+    if args.is_empty() {
+        if print || delete || path.is_some() {
+            return Err(Interrupt::ExecError("hash: a name is required".into()));
+        }
+        if clear {
+            return Ok(0);
+        }
+        if shell.hashed.is_empty() {
+            write_out("hash", b"hash: hash table empty\n")?;
+            return Ok(0);
+        }
+        let mut names: Vec<&String> = shell.hashed.keys().collect();
+        names.sort();
+        let mut out = if list {
+            String::new()
+        } else {
+            "hits\tcommand\n".to_string()
+        };
+        for name in names {
+            let (path, hits) = &shell.hashed[name];
+            if list {
+                out += &format!("builtin hash -p {} {}\n", path, name);
+            } else {
+                out += &format!("{:4}\t{}\n", hits, path);
+            }
+        }
+        write_out("hash", out.as_bytes())?;
+        return Ok(0);
+    }
+    let mut status = 0;
+    let mut out = String::new();
+    for name in args {
+        if let Some(path) = &path {
+            shell.hashed.insert(name.clone(), (path.clone(), 0));
+        } else if delete {
+            if shell.hashed.remove(name).is_none() {
+                eprintln!("hash: {}: not found", name);
+                status = 1;
+            }
+        } else if print {
+            match shell.hashed.get(name) {
+                Some((path, _)) if args.len() > 1 => out += &format!("{}\t{}\n", name, path),
+                Some((path, _)) => out += &format!("{}\n", path),
+                None => {
+                    eprintln!("hash: {}: not found", name);
+                    status = 1;
+                }
+            }
+        } else if find(name).is_none() && !name.contains('/') {
+            // Builtins are never looked up in PATH
+            match shell.search_path(name).into_iter().next() {
+                Some(path) => {
+                    shell.hashed.insert(name.clone(), (path, 0));
+                }
+                None => {
+                    eprintln!("hash: {}: not found", name);
+                    status = 1;
+                }
+            }
+        }
+    }
+    write_out("hash", out.as_bytes())?;
+    Ok(status)
+}
+
+// `set -o` shows the options, `set +o` the commands that restore them
+fn list_options(options: &Options, on: bool) -> Result<(), Interrupt> {
+    let mut out = String::new();
+    for (name, _) in OPTIONS {
+        let value = options.get(name) == Some(true);
+        if on {
+            out += &format!("{:<15}\t{}\n", name, if value { "on" } else { "off" });
+        } else {
+            out += &format!("set {}o {}\n", if value { '-' } else { '+' }, name);
+        }
+    }
+    write_out("set", out.as_bytes())
+}
+
+fn set(shell: &mut Shell, args: &[String]) -> Result<i32, Interrupt> {
+    // set [-eux] [+eux] [-o name] [+o name] [--] [arg ...]
+    if args.is_empty() {
+        let mut vars: Vec<_> = shell.vars.iter().collect();
+        vars.sort_by_key(|(name, _)| *name);
+        let mut out = String::new();
+        for (name, var) in vars {
+            match &var.value {
+                Value::Scalar(s) => out += &format!("{}={}\n", name, quote(s)),
+                Value::Array(a) => {
+                    let items: Vec<String> = a.iter().map(|s| quote(s)).collect();
+                    out += &format!("{}=({})\n", name, items.join(" "));
+                }
+            }
+        }
+        write_out("set", out.as_bytes())?;
+        return Ok(0);
+    }
+    let invalid = |msg: String| Interrupt::ExecError(format!("set: {}", msg));
+    let mut it = args.iter().peekable();
+    while let Some(arg) = it.next_if(|a| a.starts_with(['-', '+'])) {
+        let on = arg.starts_with('-');
+        if arg == "--" {
+            // Whatever follows is the positional parameters, even nothing
+            shell.args = it.cloned().collect();
+            return Ok(0);
+        }
+        for letter in arg.chars().skip(1) {
+            let name = match letter {
+                // `-o name`, which may come last in a group like `-eo pipefail`
+                'o' => match it.next_if(|a| !a.starts_with(['-', '+'])) {
+                    Some(name) => name.as_str(),
+                    None => {
+                        list_options(&shell.options, on)?;
+                        continue;
+                    }
+                },
+                _ => Options::by_letter(letter)
+                    .ok_or_else(|| invalid(format!("{}{}: invalid option", &arg[..1], letter)))?,
+            };
+            *shell
+                .options
+                .get_mut(name)
+                .ok_or_else(|| invalid(format!("{}: invalid option name", name)))? = on;
+        }
+    }
+    if it.peek().is_some() {
+        shell.args = it.cloned().collect();
+    }
+    Ok(0)
+}
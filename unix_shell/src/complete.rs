@@ -0,0 +1,229 @@
+use crate::ast::parse;
+use crate::builtin::BUILTINS;
+use crate::eval::{call_function, capture, Interrupt};
+use crate::lex::{lex, lex_spans, roles, Quote, Role, Token, Word, WordPart, RESERVED};
+use crate::shell::Shell;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+/// What is being completed at the cursor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Context {
+    Command,  // A command name
+    Argument, // Any other word
+    Redirect, // A file to redirect to or from
+    Variable, // A `$NAME` or `${NAME`
+    Job,      // A `%N` job spec
+}
+
+/// The word under the cursor and what it is for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub context: Context,
+    pub start: usize,       // Where the text being completed starts
+    pub words: Vec<String>, // The words of the command up to the cursor, as typed
+}
+
+/// How to complete the arguments of a command, set by the `complete` builtin
+#[derive(Debug, Clone, Default)]
+pub struct Spec {
+    pub words: Vec<String>,       // -W
+    pub function: Option<String>, // -F, reads `COMP_WORDS` and sets `COMPREPLY`
+    pub command: Option<String>,  // -C, prints one candidate per line
+}
+
+/// Work out what the cursor is on, using the shell's lexer on the text before it
+pub fn cursor(line: &str, pos: usize) -> Cursor {
+    let before = &line[..pos];
+    let mut spans = lex_spans(before);
+    // The word being typed, or an empty one if the cursor is after a blank or operator
+    let (start, partial) = match spans.tokens.last() {
+        Some((Token::Word(_), range)) if range.end == pos && spans.error.is_none() => {
+            (range.start, true)
+        }
+        _ if spans.error.is_some() => {
+            // An unfinished quote, the word started before it
+            let quote = spans.quotes.last().map_or(pos, |(_, r)| r.start);
+            let blank = before[..quote].rfind([' ', '\t']).map_or(0, |i| i + 1);
+            let op = spans.tokens.last().map_or(0, |(_, r)| r.end);
+            (blank.max(op), false)
+        }
+        _ => (pos, false),
+    };
+    if !partial {
+        spans.tokens.retain(|(_, r)| r.end <= start);
+        spans
+            .tokens
+            .push((Token::Word(Word::default()), start..pos));
+    }
+    let roles = roles(spans.tokens.iter().map(|(tok, _)| tok));
+    let mut words = Vec::new();
+    for ((_, range), role) in spans.tokens.iter().zip(&roles) {
+        match role {
+            Some(Role::Command) => words = vec![before[range.clone()].to_string()],
+            Some(Role::Argument) => words.push(before[range.clone()].to_string()),
+            Some(Role::Keyword) => words.clear(),
+            _ => {}
+        }
+    }
+
+    let word = &before[start..];
+    if let Some(i) = word.rfind('$') {
+        // A variable, unless it is in single quotes
+        let name = word[i + 1..].trim_start_matches('{');
+        if !word[..i].contains('\'') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Cursor {
+                context: Context::Variable,
+                start: pos - name.len(),
+                words,
+            };
+        }
+    }
+    if word.starts_with('%') && roles.last() == Some(&Some(Role::Argument)) {
+        return Cursor {
+            context: Context::Job,
+            start,
+            words,
+        };
+    }
+    let context = match roles.last() {
+        Some(Some(Role::Command | Role::Keyword)) => Context::Command,
+        Some(Some(Role::Redirect)) => Context::Redirect,
+        _ => Context::Argument,
+    };
+    Cursor {
+        context,
+        start,
+        words,
+    }
+}
+
+fn executables(path: &str, prefix: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    for dir in env::split_paths(path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) {
+                continue;
+            }
+            let executable = entry
+                .metadata()
+                .is_ok_and(|m| !m.is_dir() && m.permissions().mode() & 0o111 != 0);
+            if executable {
+                res.push(name);
+            }
+        }
+    }
+    res
+}
+
+/// Command names starting with `prefix`: keywords, builtins, functions and `PATH`
+pub fn commands(shell: &Shell, prefix: &str) -> Vec<String> {
+    let mut res: Vec<String> = RESERVED
+        .iter()
+        .chain(BUILTINS.iter().map(|(name, _)| name))
+        .map(|s| s.to_string())
+        .chain(shell.functions.keys().cloned())
+        .filter(|s| s.starts_with(prefix))
+        .collect();
+    res.extend(executables(shell.var("PATH").unwrap_or_default(), prefix));
+    res.sort();
+    res.dedup();
+    res
+}
+
+/// Variable names starting with `prefix`
+pub fn variables(shell: &Shell, prefix: &str) -> Vec<String> {
+    let mut res: Vec<String> = shell
+        .vars
+        .keys()
+        .filter(|name| name.starts_with(prefix))
+        .cloned()
+        .collect();
+    res.sort();
+    res
+}
+
+// Run the function and command of a spec, with bash-like `COMP_*` variables
+fn generate(
+    shell: &mut Shell,
+    spec: &Spec,
+    cur: &Cursor,
+    line: &str,
+    pos: usize,
+) -> Result<Vec<String>, Interrupt> {
+    let cword = cur.words.len() - 1;
+    let word = &cur.words[cword];
+    let mut res: Vec<String> = spec
+        .words
+        .iter()
+        .filter(|w| w.starts_with(word.as_str()))
+        .cloned()
+        .collect();
+    if spec.function.is_none() && spec.command.is_none() {
+        return Ok(res);
+    }
+    shell.set_array("COMP_WORDS", cur.words.clone());
+    shell.set_var("COMP_CWORD", cword.to_string());
+    shell.set_var("COMP_LINE", line.to_string());
+    shell.set_var("COMP_POINT", pos.to_string());
+    // Like bash, the arguments are the command, the word and the word before it
+    let prev = cword.checked_sub(1).map_or("", |i| cur.words[i].as_str());
+    let args = [cur.words[0].as_str(), word, prev].map(String::from);
+    let function = spec.function.as_ref().and_then(|f| shell.functions.get(f));
+    if let Some(body) = function.cloned() {
+        shell.unset_var("COMPREPLY");
+        call_function(shell, &body, &args)?;
+        res.extend(shell.array("COMPREPLY").unwrap_or_default().iter().cloned());
+    }
+    if let Some(command) = &spec.command {
+        let syntax_error = |e: &dyn fmt::Display| Interrupt::ExecError(format!("complete: {}", e));
+        let mut toks = lex(command).map_err(|e| syntax_error(&e))?;
+        toks.extend(args.iter().map(|a| {
+            Token::Word(Word(vec![WordPart {
+                text: a.clone(),
+                quote: Quote::Single,
+            }]))
+        }));
+        let list = parse(toks).map_err(|e| syntax_error(&e))?;
+        shell.export("COMP_LINE");
+        shell.export("COMP_POINT");
+        let out = capture(shell, &list)?;
+        res.extend(out.lines().map(String::from));
+    }
+    Ok(res)
+}
+
+/// Candidates for the text at `pos`, and where they start
+///
+/// `None` means the word is a file name, which is left to the filename completer.
+pub fn complete(
+    shell: &mut Shell,
+    line: &str,
+    pos: usize,
+) -> Result<Option<(usize, Vec<String>)>, Interrupt> {
+    let cur = cursor(line, pos);
+    let word = &line[cur.start..pos];
+    let candidates = match cur.context {
+        Context::Variable => variables(shell, word),
+        Context::Job => shell
+            .jobs
+            .iter()
+            .map(|j| format!("%{}", j.id))
+            .filter(|spec| spec.starts_with(word))
+            .collect(),
+        Context::Command if !word.contains('/') => commands(shell, word),
+        Context::Argument => match shell.completions.get(&cur.words[0]).cloned() {
+            Some(spec) => generate(shell, &spec, &cur, line, pos)?,
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some((cur.start, candidates)))
+}
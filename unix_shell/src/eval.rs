@@ -1,18 +1,37 @@
-use crate::ast::Proc;
-use nix::fcntl::{open, OFlag};
-use nix::libc::{STDIN_FILENO, STDOUT_FILENO};
+use crate::ast::{
+    parse, AndOr, AndOrOp, Command, Compound, List, Pipeline, Redirect, RedirectKind,
+    SimpleCommand, TimeFormat,
+};
+use crate::builtin;
+use crate::expand::{expand_pattern, expand_word, expand_words, ExpandError};
+use crate::glob;
+use crate::lex::lex;
+use crate::shell::{Shell, Var};
+use crate::trap::{self, Condition};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, open, FcntlArg, OFlag};
+use nix::libc::{rusage, wait4, STDIN_FILENO, STDOUT_FILENO};
+use nix::sys::resource::{getrusage, UsageWho};
+use nix::sys::signal::{signal, SigHandler, Signal};
 use nix::sys::stat::Mode;
-use nix::sys::wait::wait;
-use nix::unistd::{chdir, close, pipe, ForkResult};
-use nix::unistd::{dup2, execvp, fork};
-use std::ffi::{CStr, CString};
-use std::os::fd::RawFd;
+use nix::sys::time::TimeVal;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::{close, lseek, mkstemp, pipe, setpgid, unlink, write, ForkResult, Pid, Whence};
+use nix::unistd::{dup2, execve, fork};
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Read;
+use std::mem::MaybeUninit;
+use std::os::fd::{FromRawFd, RawFd};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub enum Interrupt {
     ChildError(String),
     ExecError(String),
     Exit(i32),
+    Return(i32), // From a function or a sourced file
 }
 
 type Ends = (i32, i32); // (r, w)
@@ -20,55 +39,92 @@ type Ends = (i32, i32); // (r, w)
 #[derive(Debug, Clone)]
 pub enum Output {
     Stdout,
-    File(String),
     Pipefile(Ends),
 }
 
 #[derive(Debug, Clone)]
 pub enum Input {
     Stdin,
-    File(String),
     Pipefile(Ends),
 }
 
-fn validate(cmd: &Proc, has_input: bool, has_output: bool) -> Result<(), Interrupt> {
-    match cmd {
-        Proc::SubProc(_) => Ok(()),
-        Proc::LRed(p, _) => {
-            if has_input {
-                Err(Interrupt::ExecError(format!("{:?} already has input", p)))
-            } else {
-                validate(p, true, has_output)
+fn validate_redirects(
+    name: &str,
+    redirects: &[Redirect],
+    has_input: bool,
+    has_output: bool,
+) -> Result<(), Interrupt> {
+    let (mut has_input, mut has_output) = (has_input, has_output);
+    for r in redirects {
+        match r.fd() {
+            0 if has_input => {
+                return Err(Interrupt::ExecError(format!("{} already has input", name)))
             }
-        }
-        Proc::RRed(p, _) => {
-            if has_output {
-                Err(Interrupt::ExecError(format!("{:?} already has output", p)))
-            } else {
-                validate(p, has_input, true)
+            1 if has_output => {
+                return Err(Interrupt::ExecError(format!("{} already has output", name)))
             }
+            0 => has_input = true,
+            1 => has_output = true,
+            _ => {}
         }
-        Proc::Pipe(ps) => {
-            if has_input {
-                Err(Interrupt::ExecError(format!(
-                    "{:?}: pipe cannot be fed with input",
-                    ps
-                )))
-            } else {
-                validate(ps.first().unwrap(), false, true)?;
-                for i in 1..(ps.len() - 1) {
-                    let p = &ps[i];
-                    validate(p, true, true)?;
+    }
+    Ok(())
+}
+
+fn validate(cmd: &Command, has_input: bool, has_output: bool) -> Result<(), Interrupt> {
+    match cmd {
+        Command::Simple(c) => validate_redirects(
+            &format!("{:?}", c.args),
+            &c.redirects,
+            has_input,
+            has_output,
+        ),
+        Command::Compound(c, redirects) => {
+            validate_redirects("compound command", redirects, has_input, has_output)?;
+            match c {
+                Compound::Brace(l) | Compound::Subshell(l) | Compound::For(_, _, l) => {
+                    validate_list(l)
+                }
+                Compound::Case(_, items) => items.iter().try_for_each(|i| validate_list(&i.body)),
+                Compound::If(branches, otherwise) => {
+                    for (cond, body) in branches {
+                        validate_list(cond)?;
+                        validate_list(body)?;
+                    }
+                    otherwise.iter().try_for_each(validate_list)
+                }
+                Compound::While(cond, body) | Compound::Until(cond, body) => {
+                    validate_list(cond)?;
+                    validate_list(body)
                 }
-                validate(ps.last().unwrap(), true, has_output)
             }
         }
+        Command::Function(_, body) => validate(body, false, false),
+    }
+}
+
+fn validate_pipeline(p: &Pipeline) -> Result<(), Interrupt> {
+    // Only the first command reads the input, and only the last one writes the output
+    let last = p.cmds.len() - 1;
+    for (i, cmd) in p.cmds.iter().enumerate() {
+        validate(cmd, i > 0, i < last)?;
     }
+    Ok(())
+}
+
+fn validate_list(list: &List) -> Result<(), Interrupt> {
+    for (and_or, _) in list {
+        validate_pipeline(&and_or.first)?;
+        for (_, p) in &and_or.rest {
+            validate_pipeline(p)?;
+        }
+    }
+    Ok(())
 }
 
-pub fn check_prog(cmd: &Proc) -> Result<(), Interrupt> {
+pub fn check_prog(list: &List) -> Result<(), Interrupt> {
     // Check if the command is valid
-    validate(cmd, false, false)
+    validate_list(list)
 }
 
 fn pipe_wrap() -> Result<(i32, i32), Interrupt> {
@@ -76,12 +132,36 @@ fn pipe_wrap() -> Result<(i32, i32), Interrupt> {
 }
 
 fn fork_wrap() -> Result<ForkResult, Interrupt> {
-    unsafe { fork() }.map_err(|_| Interrupt::ExecError(format!("Cannot fork")))
+    unsafe { fork() }.map_err(|_| Interrupt::ExecError(String::from("Cannot fork")))
 }
 
-fn wait_wrap() -> Result<(), Interrupt> {
-    wait().map_err(|e| Interrupt::ExecError(format!("Cannot wait, {}", e.desc())))?;
-    Ok(())
+fn timeval(t: TimeVal) -> Duration {
+    Duration::new(t.tv_sec() as u64, t.tv_usec() as u32 * 1000)
+}
+
+// Wait with `wait4`, which also tells the user and system time the child used
+fn waitpid_wrap(pid: Pid) -> Result<(WaitStatus, [Duration; 2]), Interrupt> {
+    let err = |e: Errno| Interrupt::ExecError(format!("Cannot wait, {}", e.desc()));
+    let mut status = 0;
+    let mut usage = MaybeUninit::<rusage>::zeroed();
+    loop {
+        // Safety: both pointers are to memory of the right type that lives through the call
+        let res = unsafe { wait4(pid.as_raw(), &mut status, 0, usage.as_mut_ptr()) };
+        match Errno::result(res) {
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(err(e)),
+            Ok(pid) => {
+                // Safety: filled in by `wait4`, and zeroed before
+                let usage = unsafe { usage.assume_init() };
+                let status = WaitStatus::from_raw(Pid::from_raw(pid), status).map_err(err)?;
+                let times = [
+                    timeval(TimeVal::from(usage.ru_utime)),
+                    timeval(TimeVal::from(usage.ru_stime)),
+                ];
+                return Ok((status, times));
+            }
+        }
+    }
 }
 
 fn close_wrap(fd: RawFd) -> Result<(), Interrupt> {
@@ -99,178 +179,729 @@ fn dup2_wrap(oldfd: RawFd, newfd: RawFd) -> Result<(), Interrupt> {
     Ok(())
 }
 
-pub fn eval(cmd: &Proc, input: &Input, output: &Output, non_block: bool) -> Result<(), Interrupt> {
-    match cmd {
-        Proc::SubProc((cmd, is_background)) => {
-            if cmd.is_empty() {
+fn open_output(path: &str, flags: OFlag) -> nix::Result<RawFd> {
+    open(
+        path,
+        OFlag::O_WRONLY | OFlag::O_CREAT | flags,
+        Mode::S_IRUSR | Mode::S_IWUSR | Mode::S_IWGRP | Mode::S_IRGRP | Mode::S_IROTH,
+    )
+}
+
+// A file without a name in `dir`
+fn anonymous_file(dir: &str) -> nix::Result<RawFd> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        // Some filesystems do not support it, and older kernels take it as a directory
+        match open(
+            dir,
+            OFlag::O_TMPFILE | OFlag::O_RDWR,
+            Mode::S_IRUSR | Mode::S_IWUSR,
+        ) {
+            Err(Errno::EOPNOTSUPP | Errno::EISDIR) => {}
+            res => return res,
+        }
+    }
+    let (fd, path) = mkstemp(&Path::new(dir).join("sh-heredoc.XXXXXX"))?;
+    if let Err(e) = unlink(&path) {
+        let _ = close(fd);
+        return Err(e);
+    }
+    Ok(fd)
+}
+
+fn heredoc_fd(shell: &Shell, body: &str) -> nix::Result<RawFd> {
+    // A file, so that large bodies cannot block like a pipe would
+    let dir = shell
+        .var("TMPDIR")
+        .filter(|d| !d.is_empty())
+        .unwrap_or("/tmp");
+    let fd = anonymous_file(dir)?;
+    let mut buf = body.as_bytes();
+    while !buf.is_empty() {
+        buf = &buf[write(fd, buf)?..];
+    }
+    lseek(fd, 0, Whence::SeekSet)?;
+    Ok(fd)
+}
+
+// A script stops at an expansion error, the prompt only abandons the command
+fn expand_error(shell: &Shell, e: ExpandError) -> Interrupt {
+    if shell.interactive {
+        Interrupt::ExecError(e.0)
+    } else {
+        eprintln!("Error: {}", e);
+        Interrupt::Exit(1)
+    }
+}
+
+fn redirect(shell: &mut Shell, r: &Redirect) -> Result<(), Interrupt> {
+    let fd = r.fd();
+    let target = expand_word(shell, &r.target).map_err(|e| expand_error(shell, e))?;
+    let err = |e: nix::Error| Interrupt::ExecError(format!("{}: {}", target, e.desc()));
+    let new_fd = match r.kind {
+        RedirectKind::In => open(target.as_str(), OFlag::O_RDONLY, Mode::empty()).map_err(err)?,
+        RedirectKind::Out | RedirectKind::Clobber => {
+            open_output(&target, OFlag::O_TRUNC).map_err(err)?
+        }
+        RedirectKind::Append => open_output(&target, OFlag::O_APPEND).map_err(err)?,
+        RedirectKind::ReadWrite => open(
+            target.as_str(),
+            OFlag::O_RDWR | OFlag::O_CREAT,
+            Mode::S_IRUSR | Mode::S_IWUSR | Mode::S_IWGRP | Mode::S_IRGRP | Mode::S_IROTH,
+        )
+        .map_err(err)?,
+        RedirectKind::DupIn | RedirectKind::DupOut => {
+            // n>&m duplicates m, n>&- closes n
+            if target == "-" {
+                let _ = close(fd);
                 return Ok(());
             }
-            // Match Internal Commands
-            let cmd0 = cmd[0].as_str();
-            match cmd0 {
-                "exit" if cmd.len() <= 2 => {
-                    // Exit with code
-                    if let Some(code) = cmd.get(1) {
-                        if let Ok(code) = code.parse() {
-                            Err(Interrupt::Exit(code))
-                        } else {
-                            Err(Interrupt::ExecError(String::from("Syntax Error")))
-                        }
-                    } else {
-                        // Default exit 0
-                        Err(Interrupt::Exit(0))
-                    }
-                }
-                "cd" if cmd.len() <= 2 => {
-                    // Change working dir
-                    if let Some(path) = cmd.get(1) {
-                        chdir(path.as_str()).map_err(|_| {
-                            Interrupt::ExecError(format!("Cannot cd such file or directory"))
-                        })?;
-                        Ok(())
-                    } else {
-                        // Do nothing
-                        Ok(())
+            let src: RawFd = target
+                .parse()
+                .map_err(|_| Interrupt::ExecError(format!("{}: ambiguous redirect", target)))?;
+            if src != fd {
+                dup2(src, fd).map_err(err)?;
+            }
+            return Ok(());
+        }
+        RedirectKind::HereDoc => heredoc_fd(shell, &target).map_err(|e| {
+            Interrupt::ExecError(format!("Cannot create here-document, {}", e.desc()))
+        })?,
+    };
+    if new_fd != fd {
+        dup2(new_fd, fd).map_err(err)?;
+        close_wrap(new_fd)?;
+    }
+    Ok(())
+}
+
+type SavedFds = Vec<(RawFd, Option<RawFd>)>; // (fd, copy of what it was)
+
+fn apply_redirects(
+    shell: &mut Shell,
+    redirects: &[Redirect],
+    mut saved: Option<&mut SavedFds>,
+) -> Result<(), Interrupt> {
+    for r in redirects {
+        if let Some(saved) = saved.as_deref_mut() {
+            let fd = r.fd();
+            saved.push((fd, fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(10)).ok()));
+        }
+        redirect(shell, r)?;
+    }
+    Ok(())
+}
+
+fn restore_fds(saved: SavedFds) {
+    for (fd, copy) in saved.into_iter().rev() {
+        match copy {
+            Some(copy) => {
+                let _ = dup2(copy, fd);
+                let _ = close(copy);
+            }
+            None => {
+                let _ = close(fd);
+            }
+        }
+    }
+}
+
+fn with_redirects(
+    shell: &mut Shell,
+    redirects: &[Redirect],
+    run: impl FnOnce(&mut Shell) -> Result<i32, Interrupt>,
+) -> Result<i32, Interrupt> {
+    // Redirections of commands running inside the shell itself are undone afterwards
+    if redirects.is_empty() {
+        return run(shell);
+    }
+    let mut saved = Vec::new();
+    let res = apply_redirects(shell, redirects, Some(&mut saved)).and_then(|_| run(shell));
+    restore_fds(saved);
+    res
+}
+
+pub(crate) fn exit_status(status: WaitStatus) -> Option<(Pid, i32)> {
+    match status {
+        WaitStatus::Exited(pid, code) => Some((pid, code)),
+        WaitStatus::Signaled(pid, sig, _) => Some((pid, 128 + sig as i32)),
+        _ => None,
+    }
+}
+
+fn wait_pid(shell: &mut Shell, pid: Pid) -> Result<i32, Interrupt> {
+    // Wait for exactly this child, so that background jobs are left alone
+    let (status, [user, sys]) = waitpid_wrap(pid)?;
+    shell.waited[0] += user;
+    shell.waited[1] += sys;
+    Ok(exit_status(status).map_or(0, |(_, code)| code))
+}
+
+fn wait_pids(shell: &mut Shell, pids: &[Pid]) -> Result<Vec<i32>, Interrupt> {
+    // The status of each stage of a pipeline, in order
+    pids.iter().map(|&pid| wait_pid(shell, pid)).collect()
+}
+
+fn spawn(
+    shell: &mut Shell,
+    input: &Input,
+    output: &Output,
+    run: impl FnOnce(&mut Shell) -> Result<i32, Interrupt>,
+) -> Result<Pid, Interrupt> {
+    match fork_wrap()? {
+        ForkResult::Parent { child } => {
+            // Close unused pipe ends
+            if let Input::Pipefile(fd) = input {
+                close_wrap(fd.0)?;
+            }
+            if let Output::Pipefile(fd) = output {
+                close_wrap(fd.1)?;
+            }
+            Ok(child)
+        }
+        ForkResult::Child => {
+            // Rust ignores SIGPIPE, but a child writing to a closed pipe should just die
+            // Safety: no other thread runs in the new process, and the handler is the default
+            let _ = unsafe { signal(Signal::SIGPIPE, SigHandler::SigDfl) };
+            trap::reset(shell);
+            if let Output::Pipefile(fd) = output {
+                close_child_wrap(fd.0)?;
+                dup2_wrap(fd.1, STDOUT_FILENO)?;
+                close_child_wrap(fd.1)?;
+            }
+            if let Input::Pipefile(fd) = input {
+                dup2_wrap(fd.0, STDIN_FILENO)?;
+                close_child_wrap(fd.0)?;
+            }
+            // The child never goes back to the caller
+            Err(match run(shell) {
+                Ok(status) | Err(Interrupt::Return(status)) => Interrupt::Exit(status),
+                Err(Interrupt::ExecError(e)) => Interrupt::ChildError(e),
+                Err(e) => e,
+            })
+        }
+    }
+}
+
+fn exec(
+    shell: &mut Shell,
+    redirects: &[Redirect],
+    path: &str,
+    args: &[String],
+) -> Result<i32, Interrupt> {
+    apply_redirects(shell, redirects, None)?;
+    // Convert Vec<String> to Vec<CStr>
+    let pargs: Vec<CString> = args
+        .iter()
+        .map(|x| CString::new(x.as_str()))
+        .collect::<Result<_, _>>()
+        .map_err(|_| {
+            Interrupt::ChildError(format!(
+                "Subprocess {:?} has an argument containing NUL",
+                args
+            ))
+        })?;
+    let path = CString::new(path).map_err(|_| {
+        Interrupt::ChildError(format!("Subprocess {:?} has a path containing NUL", args))
+    })?;
+    let Err(e) = execve(&path, &pargs, &shell.envp());
+    // When a process terminates, all of its open files are closed automatically by the kernel
+    Err(Interrupt::ChildError(format!(
+        "Subprocess {:?} cannot run execve: {}",
+        args,
+        e.desc()
+    )))
+}
+
+// Quote a string so that the shell reads it back unchanged
+pub(crate) fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+// Quote a traced argument only when it needs it, as bash does
+fn trace_quote(s: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%^".contains(c);
+    if !s.is_empty() && s.chars().all(plain) {
+        s.to_string()
+    } else {
+        quote(s)
+    }
+}
+
+/// Run a function body with `args` as the positional parameters
+pub fn call_function(shell: &mut Shell, body: &Command, args: &[String]) -> Result<i32, Interrupt> {
+    with_frame(shell, Some(args), |sh| eval_command(sh, body, false))
+}
+
+/// Run a function body or a sourced file, which `return` ends. `args` replace the
+/// positional parameters until then
+pub fn with_frame(
+    shell: &mut Shell,
+    args: Option<&[String]>,
+    run: impl FnOnce(&mut Shell) -> Result<i32, Interrupt>,
+) -> Result<i32, Interrupt> {
+    let saved = args.map(|args| std::mem::replace(&mut shell.args, args.to_vec()));
+    shell.frames += 1;
+    let res = run(shell);
+    shell.frames -= 1;
+    if let Some(saved) = saved {
+        shell.args = saved;
+    }
+    let res = match res {
+        Err(Interrupt::Return(status)) => Ok(status),
+        res => res,
+    };
+    if let Ok(status) = res {
+        shell.status = status;
+        trap::run(shell, Condition::Return)?;
+    }
+    res
+}
+
+/// Lex, parse, check and run a piece of shell source in this shell
+pub fn execute(shell: &mut Shell, source: &str) -> Result<i32, Interrupt> {
+    let syntax_error =
+        |e: &dyn std::fmt::Display| Interrupt::ExecError(format!("Syntax error: {}", e));
+    let toks = lex(source).map_err(|e| syntax_error(&e))?;
+    // println!("{:?}", toks); // Print the lexer result
+    if toks.is_empty() {
+        return Ok(0);
+    }
+    let list = parse(toks).map_err(|e| syntax_error(&e))?;
+    // println!("{:?}", list); // Print the AST
+    check_prog(&list)?;
+    eval(shell, &list)
+}
+
+/// Run `list` in a child process and collect what it writes to stdout
+pub fn capture(shell: &mut Shell, list: &List) -> Result<String, Interrupt> {
+    let ends = pipe_wrap()?;
+    let pid = spawn(shell, &Input::Stdin, &Output::Pipefile(ends), |sh| {
+        eval(sh, list)
+    })?;
+    let mut out = String::new();
+    // Safety: the read end belongs to us alone, the parent closed the write end in `spawn`
+    let mut file = unsafe { File::from_raw_fd(ends.0) };
+    let res = file.read_to_string(&mut out);
+    drop(file);
+    wait_pid(shell, pid)?;
+    res.map_err(|e| Interrupt::ExecError(format!("Cannot read output: {}", e)))?;
+    Ok(out)
+}
+
+fn eval_simple(shell: &mut Shell, c: &SimpleCommand, forked: bool) -> Result<i32, Interrupt> {
+    trap::run(shell, Condition::Debug)?;
+    let args = expand_words(shell, &c.args).map_err(|e| expand_error(shell, e))?;
+    // Set one by one, so that a value sees the ones before it. Before a command they are
+    // exported for that command alone, and put back after it
+    let mut saved = Vec::new();
+    let mut line = Vec::new();
+    for (name, value) in &c.assigns {
+        let value = match expand_word(shell, value) {
+            Ok(value) => value,
+            Err(e) => {
+                restore_vars(shell, saved);
+                return Err(expand_error(shell, e));
+            }
+        };
+        line.push(format!("{}={}", name, trace_quote(&value)));
+        if !args.is_empty() {
+            saved.push((name.clone(), shell.vars.get(name).cloned()));
+        }
+        shell.set_var(name, value);
+        if !args.is_empty() {
+            shell.export(name);
+        }
+    }
+    line.extend(args.iter().map(|a| trace_quote(a)));
+    if shell.options.xtrace && !line.is_empty() {
+        let ps4 = shell.var("PS4").unwrap_or("+ ");
+        eprintln!("{}{}", ps4, line.join(" "));
+    }
+    let res = eval_words(shell, c, &args, forked);
+    restore_vars(shell, saved);
+    res
+}
+
+fn restore_vars(shell: &mut Shell, saved: Vec<(String, Option<Var>)>) {
+    for (name, var) in saved.into_iter().rev() {
+        shell.restore_var(&name, var);
+    }
+}
+
+// Run a simple command once its words are expanded
+fn eval_words(
+    shell: &mut Shell,
+    c: &SimpleCommand,
+    args: &[String],
+    forked: bool,
+) -> Result<i32, Interrupt> {
+    let Some(name) = args.first() else {
+        // Only redirections, e.g. `> file` creates it
+        return with_redirects(shell, &c.redirects, |_| Ok(0));
+    };
+    if let Some(body) = shell.functions.get(name).cloned() {
+        return with_redirects(shell, &c.redirects, |sh| {
+            call_function(sh, &body, &args[1..])
+        });
+    }
+    if name == "exec" && args.len() == 1 {
+        // Without a command, the redirections stay for the rest of the shell
+        apply_redirects(shell, &c.redirects, None)?;
+        return Ok(0);
+    }
+    if let Some(run) = builtin::find(name) {
+        return with_redirects(shell, &c.redirects, |sh| match run(sh, &args[1..]) {
+            // Reported while its own redirections, like `2>/dev/null`, are still in place
+            Err(Interrupt::ExecError(e)) => {
+                eprintln!("Error: {}", e);
+                Ok(1)
+            }
+            res => res,
+        });
+    }
+    run_program(shell, &c.redirects, args, forked)
+}
+
+/// Run the program named by `args[0]`, found in `PATH` unless it is a path
+pub(crate) fn run_program(
+    shell: &mut Shell,
+    redirects: &[Redirect],
+    args: &[String],
+    forked: bool,
+) -> Result<i32, Interrupt> {
+    // Looked up before forking, so that the hash table keeps it for the next time
+    let Some(path) = shell.find_command(&args[0]) else {
+        return with_redirects(shell, redirects, |_| {
+            eprintln!("Error: {}: command not found", args[0]);
+            Ok(127)
+        });
+    };
+    if forked {
+        // Nothing else to do in this process, replace it directly
+        return exec(shell, redirects, &path, args);
+    }
+    // Execute as normal commands
+    let pid = spawn(shell, &Input::Stdin, &Output::Stdout, |sh| {
+        exec(sh, redirects, &path, args)
+    })?;
+    wait_pid(shell, pid)
+}
+
+fn eval_compound(shell: &mut Shell, c: &Compound) -> Result<i32, Interrupt> {
+    match c {
+        Compound::Brace(list) => eval(shell, list),
+        Compound::Subshell(list) => {
+            let pid = spawn(shell, &Input::Stdin, &Output::Stdout, |sh| eval(sh, list))?;
+            wait_pid(shell, pid)
+        }
+        Compound::For(name, words, body) => {
+            let items = match words {
+                Some(words) => expand_words(shell, words).map_err(|e| expand_error(shell, e))?,
+                None => shell.args.clone(),
+            };
+            let mut status = 0;
+            for item in items {
+                shell.set_var(name, item);
+                status = eval(shell, body)?;
+            }
+            Ok(status)
+        }
+        Compound::Case(word, items) => {
+            let word = expand_word(shell, word).map_err(|e| expand_error(shell, e))?;
+            for item in items {
+                let mut matched = false;
+                for p in &item.patterns {
+                    let pattern = expand_pattern(shell, p).map_err(|e| expand_error(shell, e))?;
+                    if glob::matches(&pattern, &word) {
+                        matched = true;
+                        break;
                     }
                 }
-                "cz" => {
-                    // Collect Zombie Processes
-                    let mut has_child = true;
-                    while let Ok(_) = wait() {
-                        has_child = false;
-                    }
-                    if has_child {
-                        Err(Interrupt::ExecError(format!("No child process found")))
+                if matched {
+                    return if item.body.is_empty() {
+                        Ok(0)
                     } else {
-                        Ok(())
-                    }
+                        eval(shell, &item.body)
+                    };
                 }
-                _ => {
-                    // Execute as normal commands
-                    // Creating the child process
-                    // println!(
-                    //     "[DEBUG] Forking prog {:?}, input {:?}, output {:?}",
-                    //     cmd, input, output
-                    // );
-                    let pres = fork_wrap()?;
-                    match pres {
-                        ForkResult::Parent { child: _ } => {
-                            // println!(
-                            //     "[DEBUG] Parent process, waiting for the child (pid: {}) to complete...",
-                            //     child.as_raw()
-                            // );
-                            // Close unused pipe ends
-                            if let Input::Pipefile(fd) = input {
-                                close_wrap(fd.0)?;
-                            }
-                            if let Output::Pipefile(fd) = output {
-                                close_wrap(fd.1)?;
-                            }
-                            if !is_background && !non_block {
-                                wait_wrap()?;
-                            }
-                            // println!("[DEBUG] Child process {} exited!", child.as_raw());
-                        }
-                        ForkResult::Child => {
-                            match output {
-                                Output::Stdout => {}
-                                Output::File(path) => {
-                                    // fd = open(path)
-                                    // dup2(fd, stdout)
-                                    let fd = open(
-                                        path.as_str(),
-                                        OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC,
-                                        Mode::S_IRUSR
-                                            | Mode::S_IWUSR
-                                            | Mode::S_IWGRP
-                                            | Mode::S_IRGRP
-                                            | Mode::S_IROTH,
-                                    )
-                                    .map_err(|e| {
-                                        Interrupt::ChildError(format!(
-                                            "Subprocess {:?} file {} open error: {}",
-                                            cmd,
-                                            path,
-                                            e.desc()
-                                        ))
-                                    })?;
-                                    dup2_wrap(fd, STDOUT_FILENO)?;
-                                }
-                                Output::Pipefile(fd) => {
-                                    // println!("[DEBUG] Setting output to {}", fd);
-                                    close_child_wrap(fd.0)?;
-                                    dup2_wrap(fd.1, STDOUT_FILENO)?;
-                                }
-                            }
-                            match input {
-                                Input::Stdin => {}
-                                Input::File(path) => {
-                                    let fd = open(path.as_str(), OFlag::O_RDONLY, Mode::S_IRUSR)
-                                        .map_err(|e| {
-                                            Interrupt::ChildError(format!(
-                                                "Subprocess {:?} file {} open error: {}",
-                                                cmd,
-                                                path,
-                                                e.desc()
-                                            ))
-                                        })?;
-                                    dup2_wrap(fd, STDIN_FILENO)?;
-                                }
-                                Input::Pipefile(fd) => {
-                                    // println!("[DEBUG] Setting input to {}", fd);
-                                    dup2_wrap(fd.0, STDIN_FILENO)?;
-                                }
-                            }
-                            // Convert Vec<String> to Vec<CStr>
-                            let pname = CString::new(cmd0).unwrap();
-                            let pname = pname.as_c_str();
-                            let pargs = cmd.clone();
-                            let pargs: Vec<CString> = pargs
-                                .iter()
-                                .map(|x| CString::new(x.clone()).unwrap())
-                                .collect();
-                            let pargs: Vec<&CStr> = pargs.iter().map(|x| x.as_c_str()).collect();
-                            execvp(pname, &pargs).map_err(|e| {
-                                Interrupt::ChildError(format!(
-                                    "Subprocess {:?} cannot run execvp: {}",
-                                    cmd,
-                                    e.desc()
-                                ))
-                            })?;
-                            // When a process terminates, all of its open files are closed automatically by the kernel
-                        }
-                    }
-                    Ok(())
+            }
+            Ok(0)
+        }
+        Compound::If(branches, otherwise) => {
+            for (cond, body) in branches {
+                if ignoring_errexit(shell, |sh| eval(sh, cond))? == 0 {
+                    return eval(shell, body);
                 }
             }
+            match otherwise {
+                Some(body) => eval(shell, body),
+                None => Ok(0),
+            }
         }
-        Proc::RRed(proc, path) => {
-            // proc > path
-            eval(&proc, input, &Output::File(path.clone()), non_block)
+        Compound::While(cond, body) | Compound::Until(cond, body) => {
+            let is_while = matches!(c, Compound::While(..));
+            let mut status = 0;
+            while (ignoring_errexit(shell, |sh| eval(sh, cond))? == 0) == is_while {
+                status = eval(shell, body)?;
+            }
+            Ok(status)
         }
-        Proc::LRed(proc, path) => {
-            // proc < path
-            eval(&proc, &Input::File(path.clone()), output, non_block)
+    }
+}
+
+fn eval_command(shell: &mut Shell, cmd: &Command, forked: bool) -> Result<i32, Interrupt> {
+    let res = match cmd {
+        Command::Simple(c) => eval_simple(shell, c, forked),
+        Command::Compound(c, redirects) => {
+            with_redirects(shell, redirects, |sh| eval_compound(sh, c))
+        }
+        Command::Function(name, body) => {
+            shell.functions.insert(name.clone(), body.clone());
+            Ok(0)
+        }
+    };
+    match res {
+        Err(Interrupt::ExecError(e)) => {
+            // A failed command only fails itself
+            eprintln!("Error: {}", e);
+            Ok(1)
+        }
+        res => res,
+    }
+}
+
+// Run with `errexit` ignored, for conditions and all but the last command of `&&` and `||`
+fn ignoring_errexit(
+    shell: &mut Shell,
+    run: impl FnOnce(&mut Shell) -> Result<i32, Interrupt>,
+) -> Result<i32, Interrupt> {
+    let saved = std::mem::replace(&mut shell.in_condition, true);
+    let res = run(shell);
+    shell.in_condition = saved;
+    res
+}
+
+/// CPU time used by the shell and by its children, on a wall clock
+pub(crate) struct Times {
+    pub real: Instant,
+    pub user: [Duration; 2], // Of the shell, then of its children that were waited for
+    pub sys: [Duration; 2],
+}
+
+impl Times {
+    pub fn now() -> Times {
+        let usage = |who| {
+            getrusage(who).map_or((Duration::ZERO, Duration::ZERO), |u| {
+                (timeval(u.user_time()), timeval(u.system_time()))
+            })
+        };
+        let (shell, children) = (
+            usage(UsageWho::RUSAGE_SELF),
+            usage(UsageWho::RUSAGE_CHILDREN),
+        );
+        Times {
+            real: Instant::now(),
+            user: [shell.0, children.0],
+            sys: [shell.1, children.1],
         }
-        Proc::Pipe(ps) => {
-            if ps.len() <= 1 {
-                // Invalid!
-                panic!("Invalid Pipe detected");
+    }
+
+    // For `time`, the children are only those waited for in the foreground, so that a
+    // background job reaped meanwhile is not counted
+    fn waited(shell: &Shell) -> Times {
+        let mut times = Times::now();
+        times.user[1] = shell.waited[0];
+        times.sys[1] = shell.waited[1];
+        times
+    }
+}
+
+/// Seconds with `precision` decimals, like `1.500`, or with minutes, like `0m1.500s`
+pub(crate) fn seconds(time: Duration, precision: usize, long: bool) -> String {
+    let secs = time.as_secs_f64();
+    if long {
+        let minutes = (secs / 60.0).floor();
+        format!("{}m{:.*}s", minutes, precision, secs - minutes * 60.0)
+    } else {
+        format!("{:.*}", precision, secs)
+    }
+}
+
+// Expand `TIMEFORMAT`: `%[p][l]R`, `U` and `S` are the real, user and system times with `p`
+// decimals, `%P` the share of CPU and `%%` a `%`
+fn format_times(format: &str, real: Duration, user: Duration, sys: Duration) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let precision = chars
+            .next_if(|c| c.is_ascii_digit())
+            .map_or(3, |d| d.to_digit(10).unwrap().min(3) as usize);
+        let long = chars.next_if_eq(&'l').is_some();
+        match chars.next() {
+            Some('R') => out += &seconds(real, precision, long),
+            Some('U') => out += &seconds(user, precision, long),
+            Some('S') => out += &seconds(sys, precision, long),
+            Some('P') => {
+                let cpu = (user + sys).as_secs_f64() / real.as_secs_f64().max(1e-9);
+                out += &format!("{:.2}", cpu * 100.0);
             }
-            let mut p = pipe_wrap()?;
-            eval(ps.first().unwrap(), input, &Output::Pipefile(p), true)?;
-            for id in 1..(ps.len() - 1) {
-                let cps = &ps[id];
-                let np = pipe_wrap()?;
-                eval(cps, &Input::Pipefile(p), &Output::Pipefile(np), true)?;
-                p = np;
+            Some('%') => out.push('%'),
+            Some(c) => {
+                out.push('%');
+                out.push(c);
             }
-            eval(ps.last().unwrap(), &Input::Pipefile(p), output, true)?;
-            // Wait for all the processes to finish
-            for _ in 0..ps.len() {
-                wait().map_err(|e| Interrupt::ExecError(format!("Cannot wait, {}", e.desc())))?;
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+// Report on stderr how long a `time`d pipeline took since `start`
+fn report_times(shell: &Shell, format: TimeFormat, start: &Times) {
+    let end = Times::waited(shell);
+    let format = match format {
+        TimeFormat::Posix => "real %2R\nuser %2U\nsys %2S",
+        TimeFormat::Default => shell
+            .var("TIMEFORMAT")
+            .unwrap_or("\nreal\t%3lR\nuser\t%3lU\nsys\t%3lS"),
+    };
+    // An empty format shows nothing
+    if format.is_empty() {
+        return;
+    }
+    let spent = |a: [Duration; 2], b: [Duration; 2]| (a[0] + a[1]).saturating_sub(b[0] + b[1]);
+    let real = end.real - start.real;
+    eprintln!(
+        "{}",
+        format_times(
+            format,
+            real,
+            spent(end.user, start.user),
+            spent(end.sys, start.sys)
+        )
+    );
+}
+
+fn eval_pipeline(shell: &mut Shell, p: &Pipeline, forked: bool) -> Result<i32, Interrupt> {
+    // A timed pipeline has to come back to report
+    let start = p.time.map(|_| Times::waited(shell));
+    let forked = forked && start.is_none();
+    let status = if p.bang {
+        // `! cmd` cannot make the shell exit, and the status must come back to be negated
+        let status = ignoring_errexit(shell, |sh| eval_stages(sh, p, false))?;
+        (status == 0) as i32
+    } else {
+        eval_stages(shell, p, forked)?
+    };
+    if let (Some(format), Some(start)) = (p.time, start) {
+        report_times(shell, format, &start);
+    }
+    Ok(status)
+}
+
+fn eval_stages(shell: &mut Shell, p: &Pipeline, forked: bool) -> Result<i32, Interrupt> {
+    let statuses = if let [cmd] = &p.cmds[..] {
+        vec![eval_command(shell, cmd, forked)?]
+    } else {
+        let mut pids = Vec::new();
+        let mut input = Input::Stdin;
+        for (i, cmd) in p.cmds.iter().enumerate() {
+            let output = if i == p.cmds.len() - 1 {
+                Output::Stdout
+            } else {
+                Output::Pipefile(pipe_wrap()?)
+            };
+            pids.push(spawn(shell, &input, &output, |sh| {
+                eval_command(sh, cmd, true)
+            })?);
+            if let Output::Pipefile(fd) = output {
+                input = Input::Pipefile(fd);
+            }
+        }
+        // Wait for all the processes to finish
+        wait_pids(shell, &pids)?
+    };
+    // The last stage, or with pipefail the last one that failed
+    let status = if shell.options.pipefail {
+        statuses.iter().rev().find(|&&s| s != 0).map_or(0, |&s| s)
+    } else {
+        statuses[statuses.len() - 1]
+    };
+    shell.set_array(
+        "PIPESTATUS",
+        statuses.iter().map(|s| s.to_string()).collect(),
+    );
+    Ok(status)
+}
+
+fn eval_and_or(shell: &mut Shell, and_or: &AndOr, forked: bool) -> Result<i32, Interrupt> {
+    let mut status = if and_or.rest.is_empty() {
+        eval_pipeline(shell, &and_or.first, forked)?
+    } else {
+        ignoring_errexit(shell, |sh| eval_pipeline(sh, &and_or.first, false))?
+    };
+    let mut last = &and_or.first;
+    for (i, (op, p)) in and_or.rest.iter().enumerate() {
+        shell.status = status;
+        let run = match op {
+            AndOrOp::And => status == 0,
+            AndOrOp::Or => status != 0,
+        };
+        if run {
+            status = if i == and_or.rest.len() - 1 {
+                eval_pipeline(shell, p, false)?
+            } else {
+                ignoring_errexit(shell, |sh| eval_pipeline(sh, p, false))?
+            };
+            last = p;
+        }
+    }
+    shell.status = status;
+    // Only the last pipeline of the list can make the shell exit. A brace group, `if` or
+    // loop has already exited on its own failed commands, so its status is not checked again
+    let is_group =
+        matches!(&last.cmds[..], [Command::Compound(c, _)] if !matches!(c, Compound::Subshell(_)));
+    let ran_last = and_or.rest.last().map_or(&and_or.first, |(_, p)| p);
+    // The ERR trap runs where errexit would exit
+    if status != 0 && !shell.in_condition && std::ptr::eq(last, ran_last) && !last.bang && !is_group
+    {
+        shell.status = status;
+        trap::run(shell, Condition::Err)?;
+        if shell.options.errexit {
+            return Err(Interrupt::Exit(status));
+        }
+    }
+    Ok(status)
+}
+
+pub fn eval(shell: &mut Shell, list: &List) -> Result<i32, Interrupt> {
+    let mut status = shell.status;
+    for (and_or, is_background) in list {
+        if *is_background {
+            // A job is a process group of its own, so that `kill %N` reaches all of it
+            let pid = spawn(shell, &Input::Stdin, &Output::Stdout, |sh| {
+                let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+                eval_and_or(sh, and_or, true)
+            })?;
+            // Both sides set it, whichever runs first
+            let _ = setpgid(pid, pid);
+            let id = shell.jobs.add(pid, and_or.to_string());
+            shell.last_bg = Some(pid);
+            if shell.interactive {
+                eprintln!("[{}] {}", id, pid);
             }
-            Ok(())
+            status = 0;
+        } else {
+            status = eval_and_or(shell, and_or, false)?;
         }
+        shell.status = status;
+        // A safe point to run the traps of signals caught meanwhile
+        trap::run_pending(shell)?;
     }
+    Ok(status)
 }
@@ -0,0 +1,771 @@
+use crate::glob;
+use crate::lex::{is_name, Quote, Word, WordPart};
+use crate::shell::Shell;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// An expansion that cannot be done, like an unset variable with `set -u`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpandError(pub String);
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn read_name(it: &mut Peekable<Chars>) -> String {
+    let mut name = String::new();
+    while let Some(&c) = it.peek() {
+        if !is_name_char(c) {
+            break;
+        }
+        name.push(c);
+        it.next();
+    }
+    name
+}
+
+// `name` and `index` in `${name[index]}`
+fn subscript(name: &str) -> Option<(&str, &str)> {
+    let (name, rest) = name.split_once('[')?;
+    Some((name, rest.strip_suffix(']')?))
+}
+
+// `$@`, `$*`, `${name[@]}` and `${name[*]}` stand for every element of a list
+fn is_list(name: &str) -> bool {
+    matches!(subscript(name), Some((_, "@" | "*"))) || name == "@" || name == "*"
+}
+
+// The length of the parameter at the start of `${...}`: a special one, a number or a
+// name with an optional subscript
+fn param_len(expr: &str) -> usize {
+    let first = expr.chars().next();
+    if first.is_some_and(|c| "?#$@*!-".contains(c)) {
+        return 1;
+    }
+    if first.is_some_and(|c| c.is_ascii_digit()) {
+        return expr.bytes().take_while(u8::is_ascii_digit).count();
+    }
+    let name = expr
+        .bytes()
+        .take_while(|&b| is_name_char(b as char))
+        .count();
+    if name == 0 {
+        return 0;
+    }
+    match expr[name..].strip_prefix('[').and_then(|r| r.find(']')) {
+        Some(end) => name + end + 2,
+        None => name,
+    }
+}
+
+// Read the inside of `${...}`, after the `{`, up to its matching `}`. Quotes and `\` hide
+// braces, as the lexer did, where `'` is only a character inside double quotes
+fn read_braced(it: &mut Peekable<Chars>, in_double: bool) -> Option<String> {
+    let mut text = String::new();
+    let mut depth = 1;
+    let mut in_quote = None;
+    while let Some(c) = it.next() {
+        match c {
+            '\\' if in_quote != Some('\'') => {
+                text.push(c);
+                text.push(it.next()?);
+                continue;
+            }
+            '\'' if in_quote.is_none() && in_double => {}
+            '\'' | '"' if in_quote.is_none() => in_quote = Some(c),
+            c if Some(c) == in_quote => in_quote = None,
+            '{' if in_quote.is_none() && text.ends_with('$') => depth += 1,
+            '}' if in_quote.is_none() => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(text);
+                }
+            }
+            _ => {}
+        }
+        text.push(c);
+    }
+    None
+}
+
+// Split the operand of `${v/pat/rep}` or `${v:off:len}` at the first `sep` outside of
+// quotes and nested braces
+fn split_operand(text: &str, sep: char) -> (&str, Option<&str>) {
+    let mut depth = 0;
+    let mut in_quote = None;
+    let mut escaped = false;
+    let mut prev = ' ';
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quote != Some('\'') => escaped = true,
+            '\'' | '"' if in_quote.is_none() => in_quote = Some(c),
+            c if Some(c) == in_quote => in_quote = None,
+            '{' if in_quote.is_none() && prev == '$' => depth += 1,
+            '}' if in_quote.is_none() && depth > 0 => depth -= 1,
+            c if c == sep && in_quote.is_none() && depth == 0 => {
+                return (&text[..i], Some(&text[i + c.len_utf8()..]));
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    (text, None)
+}
+
+// The word after an operator in `${...}`, with its own quotes, read as if it stood alone.
+// Inside double quotes, `'` is a plain character and `\` only escapes what it does there
+fn operand(text: &str, in_double: bool) -> Word {
+    let mut word = Word::default();
+    let push = |word: &mut Word, s: &str, quote: Quote| match word.0.last_mut() {
+        Some(part) if part.quote == quote => part.text.push_str(s),
+        _ => word.0.push(WordPart {
+            text: s.to_string(),
+            quote,
+        }),
+    };
+    let nested = if in_double {
+        Quote::Double
+    } else {
+        Quote::Unquoted
+    };
+    let mut it = text.chars().peekable();
+    while let Some(c) = it.next() {
+        match c {
+            '\\' if in_double && !it.peek().is_some_and(|c| "$`\"\\}".contains(*c)) => {
+                push(&mut word, "\\", Quote::Escaped);
+            }
+            '\\' => {
+                if let Some(c) = it.next() {
+                    push(&mut word, &c.to_string(), Quote::Escaped);
+                }
+            }
+            '\'' if in_double => push(&mut word, "'", Quote::Escaped),
+            '\'' => {
+                let quoted: String = it.by_ref().take_while(|&c| c != '\'').collect();
+                push(&mut word, &quoted, Quote::Single);
+            }
+            '"' => {
+                // Keep `""` as a part, it still makes a field
+                push(&mut word, "", Quote::Double);
+                while let Some(c) = it.next() {
+                    match c {
+                        '"' => break,
+                        '\\' if it.peek().is_some_and(|c| "$`\"\\".contains(*c)) => {
+                            push(&mut word, &it.next().unwrap().to_string(), Quote::Escaped);
+                        }
+                        '$' if it.peek() == Some(&'{') => {
+                            it.next();
+                            let inner = read_braced(&mut it, true).unwrap_or_default();
+                            push(&mut word, &format!("${{{}}}", inner), Quote::Double);
+                        }
+                        c => push(&mut word, &c.to_string(), Quote::Double),
+                    }
+                }
+            }
+            '$' if it.peek() == Some(&'{') => {
+                it.next();
+                let inner = read_braced(&mut it, in_double).unwrap_or_default();
+                push(&mut word, &format!("${{{}}}", inner), nested);
+            }
+            c => push(&mut word, &c.to_string(), Quote::Unquoted),
+        }
+    }
+    word
+}
+
+fn bad_substitution(expr: &str) -> ExpandError {
+    ExpandError(format!("${{{}}}: bad substitution", expr))
+}
+
+// Char boundaries of `s`, from the start to the end
+fn boundaries(s: &str) -> Vec<usize> {
+    s.char_indices().map(|(i, _)| i).chain([s.len()]).collect()
+}
+
+// `${v#pat}` and `${v##pat}`: without the shortest or longest prefix matching
+fn remove_prefix(value: &str, pattern: &str, longest: bool) -> String {
+    let mut ends = boundaries(value);
+    if longest {
+        ends.reverse();
+    }
+    match ends
+        .into_iter()
+        .find(|&i| glob::matches(pattern, &value[..i]))
+    {
+        Some(i) => value[i..].to_string(),
+        None => value.to_string(),
+    }
+}
+
+// `${v%pat}` and `${v%%pat}`: without the shortest or longest suffix matching
+fn remove_suffix(value: &str, pattern: &str, longest: bool) -> String {
+    let mut starts = boundaries(value);
+    if !longest {
+        starts.reverse();
+    }
+    match starts
+        .into_iter()
+        .find(|&i| glob::matches(pattern, &value[i..]))
+    {
+        Some(i) => value[..i].to_string(),
+        None => value.to_string(),
+    }
+}
+
+// `${v/pat/rep}`: the longest match at the first place it matches, or with `all` at every
+// place, is replaced. `anchor` is `#` to match at the start only, `%` at the end only
+fn replace(value: &str, pattern: &str, rep: &str, all: bool, anchor: Option<char>) -> String {
+    let bounds = boundaries(value);
+    match anchor {
+        // The longest prefix or suffix, which an empty pattern matches too
+        Some('#') => {
+            return match bounds
+                .iter()
+                .rev()
+                .find(|&&e| glob::matches(pattern, &value[..e]))
+            {
+                Some(&e) => format!("{}{}", rep, &value[e..]),
+                None => value.to_string(),
+            }
+        }
+        Some(_) => {
+            return match bounds
+                .iter()
+                .find(|&&s| glob::matches(pattern, &value[s..]))
+            {
+                Some(&s) => format!("{}{}", &value[..s], rep),
+                None => value.to_string(),
+            }
+        }
+        None if pattern.is_empty() => return value.to_string(),
+        None => {}
+    }
+    let mut out = String::new();
+    let mut pos = 0;
+    while let Some(b) = bounds.iter().position(|&b| b == pos) {
+        let end = bounds[b..]
+            .iter()
+            .rev()
+            .copied()
+            .filter(|&e| e > pos)
+            .find(|&e| glob::matches(pattern, &value[pos..e]));
+        match end {
+            Some(end) => {
+                out += rep;
+                pos = end;
+                if !all {
+                    break;
+                }
+            }
+            None if pos == value.len() => break,
+            None => {
+                let c = value[pos..].chars().next().unwrap();
+                out.push(c);
+                pos += c.len_utf8();
+            }
+        }
+    }
+    out + &value[pos..]
+}
+
+// `${v^pat}`, `${v^^pat}`, `${v,pat}` and `${v,,pat}`: the first or every character
+// matching the pattern (any character by default) in upper or lower case
+fn change_case(value: &str, pattern: &str, upper: bool, all: bool) -> String {
+    let pattern = if pattern.is_empty() { "?" } else { pattern };
+    let mut out = String::new();
+    for (i, c) in value.chars().enumerate() {
+        if (all || i == 0) && glob::matches(pattern, &c.to_string()) {
+            if upper {
+                out.extend(c.to_uppercase());
+            } else {
+                out.extend(c.to_lowercase());
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// The elements of `"$@"` and `"${name[@]}"`, which become separate fields
+fn all_fields<'a>(shell: &'a Shell, name: &str) -> Option<&'a [String]> {
+    match subscript(name) {
+        Some((array, "@")) => Some(shell.array(array).unwrap_or_default()),
+        _ if name == "@" => Some(&shell.args),
+        _ => None,
+    }
+}
+
+/// The value of `$name`, including special and positional parameters
+pub fn param(shell: &Shell, name: &str) -> Option<String> {
+    if let Some((name, index)) = subscript(name) {
+        let array = shell.array(name)?;
+        return match index {
+            "@" | "*" => Some(array.join(" ")),
+            i => array.get(i.parse::<usize>().ok()?).cloned(),
+        };
+    }
+    match name {
+        "?" => Some(shell.status.to_string()),
+        "#" => Some(shell.args.len().to_string()),
+        "-" => Some(shell.options.letters()),
+        "$" => Some(shell.pid.to_string()),
+        "!" => shell.last_bg.map(|pid| pid.to_string()),
+        "0" => Some(shell.name.clone()),
+        "@" | "*" => Some(shell.args.join(" ")),
+        n if n.bytes().all(|b| b.is_ascii_digit()) => n
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| shell.args.get(i.checked_sub(1)?))
+            .cloned(),
+        n => shell.var(n).map(String::from),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Fields,  // Unquoted expansions are split on IFS
+    Single,  // The whole word is one string
+    Pattern, // Quoted text is escaped so that it only matches itself
+}
+
+struct Expander<'a> {
+    shell: &'a Shell,
+    mode: Mode,
+    fields: Vec<String>,
+    cur: String,
+    has_cur: bool, // `""` still makes a field
+    error: Option<ExpandError>,
+    assigned: Vec<(String, String)>, // By `${name:=word}`, for the shell once expansion is done
+}
+
+impl Expander<'_> {
+    fn push_str(&mut self, s: &str, quoted: bool) {
+        if quoted && self.mode == Mode::Pattern {
+            self.cur.push_str(&glob::escape(s));
+        } else {
+            self.cur.push_str(s);
+        }
+        self.has_cur = true;
+    }
+
+    fn finish(&mut self) {
+        if self.has_cur {
+            self.fields.push(std::mem::take(&mut self.cur));
+            self.has_cur = false;
+        }
+    }
+
+    fn push_expansion(&mut self, s: &str, quoted: bool) {
+        if quoted || self.mode != Mode::Fields {
+            return self.push_str(s, quoted);
+        }
+        // IFS whitespace collapses, and a field it ended takes the next other IFS character
+        // with it. Any other IFS character ends exactly one field, even an empty one
+        let ifs = self.shell.var("IFS").unwrap_or(" \t\n");
+        let mut delimited = false;
+        for c in s.chars() {
+            if !ifs.contains(c) {
+                self.cur.push(c);
+                self.has_cur = true;
+                delimited = false;
+            } else if " \t\n".contains(c) {
+                if self.has_cur {
+                    self.finish();
+                    delimited = true;
+                }
+            } else if delimited {
+                delimited = false;
+            } else {
+                self.has_cur = true;
+                self.finish();
+            }
+        }
+    }
+
+    fn fail(&mut self, e: ExpandError) {
+        if self.error.is_none() {
+            self.error = Some(e);
+        }
+    }
+
+    // The value of a parameter, as a list of elements for `$@` and the like. `None` when it
+    // is unset, or an empty list
+    fn values(&self, name: &str) -> Option<Vec<String>> {
+        if is_list(name) {
+            let list = match subscript(name) {
+                Some((array, _)) => self.shell.array(array).unwrap_or_default(),
+                None => &self.shell.args,
+            };
+            return Some(list.to_vec()).filter(|l| !l.is_empty());
+        }
+        match self.assigned.iter().rev().find(|(n, _)| n == name) {
+            Some((_, value)) => Some(vec![value.clone()]),
+            None => param(self.shell, name).map(|value| vec![value]),
+        }
+    }
+
+    // The values of a parameter, or nothing for an unset one, which is an error with `set -u`
+    fn values_or_empty(&mut self, name: &str) -> Vec<String> {
+        match self.values(name) {
+            Some(values) => values,
+            // `$@` and `$*` are only empty, even with `set -u`
+            None if is_list(name) => Vec::new(),
+            None => {
+                if self.shell.options.nounset {
+                    self.fail(ExpandError(format!("{}: unbound variable", name)));
+                }
+                vec![String::new()]
+            }
+        }
+    }
+
+    fn push_values(&mut self, name: &str, values: &[String], quoted: bool) {
+        let at = name == "@" || matches!(subscript(name), Some((_, "@")));
+        if at && quoted {
+            // "$@" keeps every argument as its own field
+            for (i, arg) in values.iter().enumerate() {
+                if i > 0 {
+                    self.has_cur = true;
+                    self.finish();
+                }
+                self.push_str(arg, true);
+            }
+            return;
+        }
+        self.push_expansion(&values.join(" "), quoted);
+    }
+
+    fn push_param(&mut self, name: &str, quoted: bool) {
+        let values = self.values_or_empty(name);
+        self.push_values(name, &values, quoted);
+    }
+
+    // Expand a word on its own, into one string or a pattern, as part of this expansion
+    fn apart(&mut self, word: &Word, mode: Mode) -> String {
+        let mut ex = expander(self.shell, mode);
+        ex.assigned = std::mem::take(&mut self.assigned);
+        ex.expand(word);
+        self.assigned = ex.assigned;
+        if let Some(e) = ex.error {
+            self.fail(e);
+        }
+        ex.cur
+    }
+
+    // The word of `${v:-word}` and `${v:+word}`, split into fields like the rest unless quoted
+    fn push_word(&mut self, word: &Word, quoted: bool) {
+        if quoted || self.mode != Mode::Fields {
+            let value = self.apart(word, Mode::Single);
+            self.push_str(&value, quoted);
+            return;
+        }
+        for part in &word.0 {
+            let part = Word(vec![part.clone()]);
+            if part.0[0].quote == Quote::Unquoted {
+                // Split like the result of any expansion
+                let value = self.apart(&part, Mode::Single);
+                self.push_expansion(&value, false);
+            } else {
+                self.expand(&part);
+            }
+        }
+    }
+
+    fn push_braced(&mut self, expr: &str, quoted: bool) {
+        if let Err(e) = self.braced(expr, quoted) {
+            self.fail(e);
+        }
+    }
+
+    // Everything `${...}` can hold besides a name
+    fn braced(&mut self, expr: &str, quoted: bool) -> Result<(), ExpandError> {
+        let bad = || bad_substitution(expr);
+        // ${#name} is a length, where ${#} alone is `$#`
+        if let Some(name) = expr.strip_prefix('#').filter(|n| !n.is_empty()) {
+            if param_len(name) == name.len() {
+                let values = self.values_or_empty(name);
+                let len = if is_list(name) {
+                    values.len()
+                } else {
+                    values[0].chars().count()
+                };
+                self.push_expansion(&len.to_string(), quoted);
+                return Ok(());
+            }
+        }
+        let len = param_len(expr);
+        if len == 0 {
+            return Err(bad());
+        }
+        let (name, op) = expr.split_at(len);
+        let mut ops = op.chars();
+        let Some(first) = ops.next() else {
+            self.push_param(name, quoted);
+            return Ok(());
+        };
+        let rest = ops.as_str();
+        // `:-`, `:=`, `:?` and `:+` also take an empty value as unset, a bare `:` takes a
+        // substring
+        let (colon, first, rest) = match (first, rest.chars().next()) {
+            (':', Some(c)) if "-=?+".contains(c) => (true, c, &rest[1..]),
+            (':', _) => return self.substring(expr, name, rest, quoted),
+            _ => (false, first, rest),
+        };
+        if "-=?+".contains(first) {
+            let values = self.values(name);
+            let unset = match &values {
+                None => true,
+                Some(values) => colon && values.concat().is_empty(),
+            };
+            // The word is quoted like the expansion, patterns below have their own quoting
+            let word = operand(rest, quoted);
+            match first {
+                '-' if unset => self.push_word(&word, quoted),
+                '+' if !unset => self.push_word(&word, quoted),
+                '+' => {}
+                '=' if unset => {
+                    if !is_name(name) {
+                        return Err(ExpandError(format!("${}: cannot assign in this way", name)));
+                    }
+                    let value = self.apart(&word, Mode::Single);
+                    self.assigned.push((name.to_string(), value.clone()));
+                    self.push_expansion(&value, quoted);
+                }
+                '?' if unset => {
+                    let message = self.apart(&word, Mode::Single);
+                    let message = if message.is_empty() {
+                        "parameter null or not set".to_string()
+                    } else {
+                        message
+                    };
+                    return Err(ExpandError(format!("{}: {}", name, message)));
+                }
+                _ => self.push_values(name, &values.unwrap_or_default(), quoted),
+            }
+            return Ok(());
+        }
+        let values = self.values_or_empty(name);
+        let values: Vec<String> = match first {
+            '#' | '%' => {
+                let longest = rest.starts_with(first);
+                let rest = if longest { &rest[1..] } else { rest };
+                let pattern = self.apart(&operand(rest, false), Mode::Pattern);
+                values
+                    .iter()
+                    .map(|v| match first {
+                        '#' => remove_prefix(v, &pattern, longest),
+                        _ => remove_suffix(v, &pattern, longest),
+                    })
+                    .collect()
+            }
+            '/' => {
+                let (all, anchor, rest) = match rest.chars().next() {
+                    Some('/') => (true, None, &rest[1..]),
+                    Some(c @ ('#' | '%')) => (false, Some(c), &rest[1..]),
+                    _ => (false, None, rest),
+                };
+                let (pattern, rep) = split_operand(rest, '/');
+                let pattern = self.apart(&operand(pattern, false), Mode::Pattern);
+                let rep = self.apart(&operand(rep.unwrap_or_default(), false), Mode::Single);
+                values
+                    .iter()
+                    .map(|v| replace(v, &pattern, &rep, all, anchor))
+                    .collect()
+            }
+            '^' | ',' => {
+                let all = rest.starts_with(first);
+                let rest = if all { &rest[1..] } else { rest };
+                let pattern = self.apart(&operand(rest, false), Mode::Pattern);
+                values
+                    .iter()
+                    .map(|v| change_case(v, &pattern, first == '^', all))
+                    .collect()
+            }
+            _ => return Err(bad()),
+        };
+        self.push_values(name, &values, quoted);
+        Ok(())
+    }
+
+    // `${v:offset}` and `${v:offset:length}`, in characters, or in elements for `$@` (from
+    // `$0`) and arrays. A negative offset counts from the end, a negative length too
+    fn substring(
+        &mut self,
+        expr: &str,
+        name: &str,
+        spec: &str,
+        quoted: bool,
+    ) -> Result<(), ExpandError> {
+        let (offset, length) = split_operand(spec, ':');
+        let mut number = |text: &str| {
+            let text = self.apart(&operand(text, false), Mode::Single);
+            text.trim()
+                .parse::<i64>()
+                .map_err(|_| bad_substitution(expr))
+        };
+        let offset = number(offset)?;
+        let length = length.map(&mut number).transpose()?;
+        let negative = || ExpandError(format!("{}: substring expression < 0", length.unwrap()));
+        // A negative length that ends before the start is an error
+        let slice = |len: usize| -> Result<Option<(usize, usize)>, ExpandError> {
+            let len = len as i64;
+            let start = if offset < 0 { len + offset } else { offset };
+            if start < 0 || start > len {
+                return Ok(None);
+            }
+            let end = match length {
+                None => len,
+                Some(l) if l < 0 && len + l < start => return Err(negative()),
+                Some(l) if l < 0 => len + l,
+                Some(l) => (start + l).min(len),
+            };
+            Ok(Some((start as usize, end as usize)))
+        };
+        if is_list(name) {
+            let mut values = self.values_or_empty(name);
+            if subscript(name).is_none() {
+                values.insert(0, self.shell.name.clone());
+            }
+            if length.is_some_and(|l| l < 0) {
+                return Err(negative());
+            }
+            let values = match slice(values.len())? {
+                Some((start, end)) => values[start..end].to_vec(),
+                None => Vec::new(),
+            };
+            self.push_values(name, &values, quoted);
+            return Ok(());
+        }
+        let value = self.values_or_empty(name).remove(0);
+        let chars: Vec<char> = value.chars().collect();
+        let value: String = match slice(chars.len())? {
+            Some((start, end)) => chars[start..end].iter().collect(),
+            None => String::new(),
+        };
+        self.push_expansion(&value, quoted);
+        Ok(())
+    }
+
+    fn expand_text(&mut self, text: &str, quoted: bool) {
+        let mut it = text.chars().peekable();
+        let mut lit = String::new();
+        while let Some(c) = it.next() {
+            if c != '$' {
+                lit.push(c);
+                continue;
+            }
+            let name = match it.peek() {
+                Some('{') => {
+                    it.next();
+                    let Some(expr) = read_braced(&mut it, quoted).filter(|e| !e.is_empty()) else {
+                        // Not a parameter, keep as is
+                        lit.push_str("${");
+                        continue;
+                    };
+                    if !lit.is_empty() {
+                        self.push_str(&std::mem::take(&mut lit), quoted);
+                    }
+                    self.push_braced(&expr, quoted);
+                    continue;
+                }
+                Some(&c) if c.is_ascii_digit() || "?#$@*!-".contains(c) => {
+                    it.next();
+                    c.to_string()
+                }
+                Some(&c) if is_name_char(c) => read_name(&mut it),
+                _ => {
+                    lit.push('$');
+                    continue;
+                }
+            };
+            if !lit.is_empty() {
+                self.push_str(&std::mem::take(&mut lit), quoted);
+            }
+            self.push_param(&name, quoted);
+        }
+        if !lit.is_empty() {
+            self.push_str(&lit, quoted);
+        }
+    }
+
+    fn expand(&mut self, word: &Word) {
+        for part in &word.0 {
+            match part.quote {
+                Quote::Unquoted => self.expand_text(&part.text, false),
+                Quote::Double => {
+                    // `"$@"` with no arguments makes no field at all
+                    let name = part.text.strip_prefix('$').map(|n| {
+                        n.strip_prefix('{')
+                            .and_then(|n| n.strip_suffix('}'))
+                            .unwrap_or(n)
+                    });
+                    if name.and_then(|n| all_fields(self.shell, n)).is_none() {
+                        self.has_cur = true;
+                    }
+                    self.expand_text(&part.text, true);
+                }
+                Quote::Escaped | Quote::Single | Quote::AnsiC => self.push_str(&part.text, true),
+            }
+        }
+    }
+}
+
+fn expander(shell: &Shell, mode: Mode) -> Expander<'_> {
+    Expander {
+        shell,
+        mode,
+        fields: Vec::new(),
+        cur: String::new(),
+        has_cur: false,
+        error: None,
+        assigned: Vec::new(),
+    }
+}
+
+// Assign what `${name:=word}` set, once the expander is done with the shell
+fn finish_expansion<T>(
+    shell: &mut Shell,
+    (error, assigned): (Option<ExpandError>, Vec<(String, String)>),
+    value: T,
+) -> Result<T, ExpandError> {
+    for (name, value) in assigned {
+        shell.set_var(&name, value);
+    }
+    error.map_or(Ok(value), Err)
+}
+
+/// Expand words into fields, splitting the unquoted results of expansions
+pub fn expand_words(shell: &mut Shell, words: &[Word]) -> Result<Vec<String>, ExpandError> {
+    let mut ex = expander(shell, Mode::Fields);
+    for word in words {
+        ex.expand(word);
+        ex.finish();
+    }
+    let fields = std::mem::take(&mut ex.fields);
+    finish_expansion(shell, (ex.error, ex.assigned), fields)
+}
+
+/// Expand a word into exactly one string, without field splitting
+pub fn expand_word(shell: &mut Shell, word: &Word) -> Result<String, ExpandError> {
+    let mut ex = expander(shell, Mode::Single);
+    ex.expand(word);
+    let value = std::mem::take(&mut ex.cur);
+    finish_expansion(shell, (ex.error, ex.assigned), value)
+}
+
+/// Expand a word into a pattern for `glob::matches`
+pub fn expand_pattern(shell: &mut Shell, word: &Word) -> Result<String, ExpandError> {
+    let mut ex = expander(shell, Mode::Pattern);
+    ex.expand(word);
+    let value = std::mem::take(&mut ex.cur);
+    finish_expansion(shell, (ex.error, ex.assigned), value)
+}
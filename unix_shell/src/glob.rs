@@ -0,0 +1,121 @@
+// Shell pattern matching: `*`, `?`, `[...]` and `\` quoting
+
+fn class_matches(class: &str, c: char) -> bool {
+    match class {
+        "alpha" => c.is_alphabetic(),
+        "digit" => c.is_ascii_digit(),
+        "alnum" => c.is_alphanumeric(),
+        "upper" => c.is_uppercase(),
+        "lower" => c.is_lowercase(),
+        "space" => c.is_whitespace(),
+        "blank" => c == ' ' || c == '\t',
+        "punct" => c.is_ascii_punctuation(),
+        "print" => !c.is_control(),
+        "graph" => !c.is_control() && !c.is_whitespace(),
+        "cntrl" => c.is_control(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+// Matches `c` against the bracket expression starting at p[start] == '['
+// Returns whether it matched and where the expression ends, or None if it is not closed
+fn bracket(p: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = matches!(p.get(i), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut lo = *p.get(i)?;
+        if lo == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if lo == '[' && p.get(i + 1) == Some(&':') {
+            // [:class:]
+            let rest: String = p[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                matched |= class_matches(&rest[..end], c);
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+        if lo == '\\' {
+            i += 1;
+            lo = *p.get(i)?;
+        }
+        i += 1;
+        let mut hi = lo;
+        if p.get(i) == Some(&'-') && p.get(i + 1).is_some_and(|&c| c != ']') {
+            hi = p[i + 1];
+            if hi == '\\' {
+                hi = *p.get(i + 2)?;
+                i += 1;
+            }
+            i += 2;
+        }
+        matched |= lo <= c && c <= hi;
+    }
+}
+
+// Where the pattern continues after matching `c` at p[pi], if it does
+fn step(p: &[char], pi: usize, c: char) -> Option<usize> {
+    match *p.get(pi)? {
+        '?' => Some(pi + 1),
+        '[' => match bracket(p, pi, c) {
+            Some((matched, next)) => matched.then_some(next),
+            None => (c == '[').then_some(pi + 1),
+        },
+        '\\' if pi + 1 < p.len() => (p[pi + 1] == c).then_some(pi + 2),
+        pc => (pc == c).then_some(pi + 1),
+    }
+}
+
+/// Whether `text` matches the shell pattern, in which `\` quotes the next character
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // The last `*` seen and the text position it currently stands for
+    let mut star = None;
+    loop {
+        if p.get(pi) == Some(&'*') {
+            star = Some((pi, ti));
+            pi += 1;
+            continue;
+        }
+        if ti == t.len() {
+            break;
+        }
+        if let Some(next) = step(&p, pi, t[ti]) {
+            pi = next;
+            ti += 1;
+            continue;
+        }
+        // Let the last `*` eat one more character
+        match star {
+            Some((sp, st)) => {
+                pi = sp + 1;
+                ti = st + 1;
+                star = Some((sp, st + 1));
+            }
+            None => return false,
+        }
+    }
+    pi == p.len()
+}
+
+/// Quote the characters that are special in patterns
+pub fn escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
@@ -0,0 +1,200 @@
+use crate::lex::{lex_spans, roles, Quote, Role, Token};
+use std::env;
+use std::ops::Range;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// SGR parameters (the `1;32` in `\x1b[1;32m`) for each kind of text
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub command: String, // Found in PATH or a builtin
+    pub unknown: String, // A command that cannot be found
+    pub keyword: String,
+    pub string: String,
+    pub operator: String,
+    pub redirect: String, // The target of a redirection
+    pub variable: String,
+    pub comment: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            command: "1;32".into(),
+            unknown: "1;31".into(),
+            keyword: "1;35".into(),
+            string: "33".into(),
+            operator: "36".into(),
+            redirect: "4".into(),
+            variable: "34".into(),
+            comment: "2;37".into(),
+        }
+    }
+}
+
+impl Theme {
+    /// Override the defaults with a spec like `command=32:string=1;33`, as in `LS_COLORS`
+    pub fn parse(spec: &str) -> Theme {
+        let mut theme = Theme::default();
+        for item in spec.split(':') {
+            let Some((key, value)) = item.split_once('=') else {
+                continue;
+            };
+            let field = match key.trim() {
+                "command" => &mut theme.command,
+                "unknown" => &mut theme.unknown,
+                "keyword" => &mut theme.keyword,
+                "string" => &mut theme.string,
+                "operator" => &mut theme.operator,
+                "redirect" => &mut theme.redirect,
+                "variable" => &mut theme.variable,
+                "comment" => &mut theme.comment,
+                _ => continue,
+            };
+            *field = value.trim().to_string();
+        }
+        theme
+    }
+}
+
+pub(crate) fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// Whether `name` is a path to an executable, or an executable somewhere in `PATH`
+pub fn in_path(name: &str) -> bool {
+    if name.contains('/') {
+        return is_executable(Path::new(name));
+    }
+    let Some(path) = env::var_os("PATH") else {
+        return false;
+    };
+    !name.is_empty() && env::split_paths(&path).any(|dir| is_executable(&dir.join(name)))
+}
+
+// `$NAME`, `${...}`, `$1` and `$?` inside `range`, skipping `\$`
+fn variables(line: &str, range: Range<usize>) -> Vec<Range<usize>> {
+    let mut res = Vec::new();
+    let text = &line[range.clone()];
+    let mut it = text.char_indices().peekable();
+    while let Some((i, c)) = it.next() {
+        match c {
+            '\\' => {
+                it.next();
+            }
+            '$' => {
+                let mut end = i + 1;
+                match it.peek() {
+                    Some(&(_, '{')) => {
+                        for (j, c) in it.by_ref() {
+                            end = j + 1;
+                            if c == '}' {
+                                break;
+                            }
+                        }
+                    }
+                    Some(&(j, c)) if c.is_ascii_digit() || "?#$@*!-".contains(c) => {
+                        it.next();
+                        end = j + 1;
+                    }
+                    _ => {
+                        while let Some(&(j, c)) = it.peek() {
+                            if !(c.is_ascii_alphanumeric() || c == '_') {
+                                break;
+                            }
+                            it.next();
+                            end = j + 1;
+                        }
+                    }
+                }
+                if end > i + 1 {
+                    res.push(range.start + i..range.start + end);
+                }
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+fn paint<'t>(styles: &mut [Option<&'t str>], range: Range<usize>, style: &'t str) {
+    for s in &mut styles[range] {
+        *s = Some(style);
+    }
+}
+
+/// Colour a line of input with ANSI escapes, using the shell's own lexer
+pub fn highlight(line: &str, theme: &Theme, is_command: impl Fn(&str) -> bool) -> String {
+    let spans = lex_spans(line);
+    let mut styles: Vec<Option<&str>> = vec![None; line.len()];
+    let mut words = Vec::new(); // Where `$` expansions may happen
+    let roles = roles(spans.tokens.iter().map(|(tok, _)| tok));
+    for ((tok, range), role) in spans.tokens.iter().zip(roles) {
+        let range = range.clone();
+        let style = match (tok, role) {
+            (Token::Word(w), Some(role)) => {
+                words.push(range.clone());
+                match role {
+                    Role::Command if is_command(&w.literal()) => &theme.command,
+                    Role::Command => &theme.unknown,
+                    Role::Keyword => &theme.keyword,
+                    Role::Redirect => &theme.redirect,
+                    Role::Argument => continue,
+                }
+            }
+            (Token::Newline, _) => continue,
+            _ => &theme.operator,
+        };
+        paint(&mut styles, range, style);
+    }
+    for (quote, range) in &spans.quotes {
+        paint(&mut styles, range.clone(), &theme.string);
+        if *quote == Quote::Double
+            && !words
+                .iter()
+                .any(|w| w.start <= range.start && range.end <= w.end)
+        {
+            // A here-document body
+            words.push(range.clone());
+        }
+    }
+    let literal: Vec<Range<usize>> = spans
+        .quotes
+        .iter()
+        .filter(|(q, _)| matches!(q, Quote::Single | Quote::AnsiC))
+        .map(|(_, r)| r.clone())
+        .collect();
+    for word in words {
+        for var in variables(line, word) {
+            if !literal
+                .iter()
+                .any(|r| r.start <= var.start && var.end <= r.end)
+            {
+                paint(&mut styles, var, &theme.variable);
+            }
+        }
+    }
+    for range in &spans.comments {
+        paint(&mut styles, range.clone(), &theme.comment);
+    }
+
+    let mut res = String::with_capacity(line.len() * 2);
+    let mut cur = None;
+    for (i, c) in line.char_indices() {
+        if styles[i] != cur {
+            if cur.is_some() {
+                res.push_str("\x1b[0m");
+            }
+            if let Some(style) = styles[i] {
+                res.push_str(&format!("\x1b[{}m", style));
+            }
+            cur = styles[i];
+        }
+        res.push(c);
+    }
+    if cur.is_some() {
+        res.push_str("\x1b[0m");
+    }
+    res
+}
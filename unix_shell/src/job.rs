@@ -0,0 +1,156 @@
+use nix::errno::Errno;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+
+/// How a reaped job ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Status(WaitStatus),
+    Unknown, // Someone else reaped it, as when `SIGCHLD` is ignored
+}
+
+/// A command started in the background with `&`
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: usize, // The N in %N
+    pub pid: Pid,  // Also the id of its process group
+    pub text: String,
+    pub done: Option<Outcome>, // Set once it has been reaped
+}
+
+impl Job {
+    /// What `jobs` and the completion notice say about it, like `Done` or `Exit 2`
+    pub fn state(&self) -> String {
+        match self.done {
+            None => "Running".into(),
+            Some(Outcome::Unknown) => "Unknown status".into(),
+            Some(Outcome::Status(WaitStatus::Exited(_, 0))) => "Done".into(),
+            Some(Outcome::Status(WaitStatus::Exited(_, code))) => format!("Exit {}", code),
+            Some(Outcome::Status(WaitStatus::Signaled(_, sig, core))) => {
+                let name = match sig {
+                    Signal::SIGHUP => "Hangup".into(),
+                    Signal::SIGINT => "Interrupt".into(),
+                    Signal::SIGKILL => "Killed".into(),
+                    Signal::SIGSEGV => "Segmentation fault".into(),
+                    Signal::SIGPIPE => "Broken pipe".into(),
+                    Signal::SIGTERM => "Terminated".into(),
+                    sig => sig.to_string(),
+                };
+                if core {
+                    name + " (core dumped)"
+                } else {
+                    name
+                }
+            }
+            Some(_) => "Done".into(),
+        }
+    }
+
+    /// Block until it ends, unless it was already reaped
+    pub fn wait(&mut self) -> nix::Result<Outcome> {
+        while self.done.is_none() {
+            match waitpid(self.pid, None) {
+                Err(Errno::EINTR) => {}
+                Err(Errno::ECHILD) => self.done = Some(Outcome::Unknown),
+                Err(e) => return Err(e),
+                Ok(status) => self.done = Some(Outcome::Status(status)),
+            }
+        }
+        Ok(self.done.unwrap())
+    }
+}
+
+/// The background jobs of the shell, in the order they were started
+#[derive(Debug, Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+}
+
+impl Jobs {
+    pub fn add(&mut self, pid: Pid, text: String) -> usize {
+        let id = self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        self.jobs.push(Job {
+            id,
+            pid,
+            text,
+            done: None,
+        });
+        id
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Job> {
+        self.jobs.iter_mut()
+    }
+
+    /// The job named by a spec: `%N`, `%%` or `%+` (current), `%-` (previous),
+    /// `%name` (its command starts with name) or `%?text` (its command contains text)
+    pub fn find(&mut self, spec: &str) -> Option<&mut Job> {
+        let spec = spec.strip_prefix('%')?;
+        let n = self.jobs.len();
+        let index =
+            match spec {
+                "" | "%" | "+" => n.checked_sub(1),
+                "-" => n.checked_sub(2),
+                _ if spec.starts_with(|c: char| c.is_ascii_digit()) => {
+                    let id: usize = spec.parse().ok()?;
+                    self.jobs.iter().position(|j| j.id == id)
+                }
+                _ => {
+                    let mut matching = self.jobs.iter().enumerate().filter(|(_, j)| {
+                        match spec.strip_prefix('?') {
+                            Some(text) => j.text.contains(text),
+                            None => j.text.starts_with(spec),
+                        }
+                    });
+                    // An ambiguous spec names no job
+                    match (matching.next(), matching.next()) {
+                        (Some((i, _)), None) => Some(i),
+                        _ => None,
+                    }
+                }
+            };
+        self.jobs.get_mut(index?)
+    }
+
+    /// `+` for the current job (the latest), `-` for the one before, as in `[1]+`
+    pub fn mark(&self, id: usize) -> char {
+        let mut ids = self.jobs.iter().rev().map(|j| j.id);
+        if ids.next() == Some(id) {
+            '+'
+        } else if ids.next() == Some(id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    /// Collect the jobs that have exited, without blocking
+    pub fn reap(&mut self) {
+        for job in self.jobs.iter_mut().filter(|j| j.done.is_none()) {
+            match waitpid(job.pid, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::StillAlive) => {}
+                Ok(status) => job.done = Some(Outcome::Status(status)),
+                Err(Errno::ECHILD) => job.done = Some(Outcome::Unknown),
+                // Tried again before the next prompt
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Remove the finished jobs, each with the mark it had
+    pub fn take_done(&mut self) -> Vec<(Job, char)> {
+        let marked: Vec<(Job, char)> = self
+            .jobs
+            .iter()
+            .filter(|j| j.done.is_some())
+            .map(|j| (j.clone(), self.mark(j.id)))
+            .collect();
+        self.jobs.retain(|j| j.done.is_none());
+        marked
+    }
+}
@@ -1,84 +1,753 @@
-#[derive(Debug)]
+use std::fmt;
+use std::ops::Range;
+
+/// How a piece of a word was quoted in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quote {
+    /// Plain text, subject to every expansion
+    Unquoted,
+    /// A single character escaped by `\`
+    Escaped,
+    /// `'...'`, fully literal
+    Single,
+    /// `"..."`, only `$` expansion happens inside
+    Double,
+    /// `$'...'`, escapes already decoded
+    AnsiC,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordPart {
+    pub text: String,
+    pub quote: Quote,
+}
+
+/// A shell word made of differently quoted pieces, e.g. `a"$b"'c'`
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Word(pub Vec<WordPart>);
+
+impl Word {
+    fn push(&mut self, c: char, quote: Quote) {
+        match self.0.last_mut() {
+            Some(part) if part.quote == quote => part.text.push(c),
+            _ => self.0.push(WordPart {
+                text: c.to_string(),
+                quote,
+            }),
+        }
+    }
+
+    fn open(&mut self, quote: Quote) {
+        // Keep `""` as an (empty) part so that it still forms a word
+        self.0.push(WordPart {
+            text: String::new(),
+            quote,
+        });
+    }
+
+    /// The text of the word with quotes removed and nothing expanded
+    pub fn literal(&self) -> String {
+        self.0.iter().map(|p| p.text.as_str()).collect()
+    }
+
+    pub fn is_quoted(&self) -> bool {
+        self.0.iter().any(|p| p.quote != Quote::Unquoted)
+    }
+}
+
+impl fmt::Debug for Word {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.literal())
+    }
+}
+
+impl fmt::Display for Word {
+    /// The word as shell source, quoted so that it reads back the same
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for part in &self.0 {
+            match part.quote {
+                Quote::Unquoted => write!(f, "{}", part.text)?,
+                Quote::Escaped => {
+                    for c in part.text.chars() {
+                        write!(f, "\\{}", c)?;
+                    }
+                }
+                Quote::Single => write!(f, "'{}'", part.text)?,
+                Quote::Double => write!(f, "\"{}\"", part.text)?,
+                Quote::AnsiC => {
+                    write!(f, "$'")?;
+                    for c in part.text.chars() {
+                        match c {
+                            '\\' | '\'' => write!(f, "\\{}", c)?,
+                            '\n' => write!(f, "\\n")?,
+                            '\t' => write!(f, "\\t")?,
+                            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                            c => write!(f, "{}", c)?,
+                        }
+                    }
+                    write!(f, "'")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<&str> for Word {
+    fn from(s: &str) -> Self {
+        Word(vec![WordPart {
+            text: s.to_string(),
+            quote: Quote::Unquoted,
+        }])
+    }
+}
+
+/// The body of a here-document, read from the lines after the operator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HereDoc {
+    pub strip_tabs: bool, // <<-
+    pub body: Word,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
-    Str(String),
+    Word(Word),
+    IoNumber(i32), // The `2` in `2>file`
     Pipe,
-    RightRedirect,
-    LeftRedirect,
-    Background,
+    AndIf,      // &&
+    OrIf,       // ||
+    Semi,       // ;
+    DSemi,      // ;;
+    Background, // &
+    Newline,
+    LParen,
+    RParen,
+    LeftRedirect,     // <
+    RightRedirect,    // >
+    Append,           // >>
+    DupIn,            // <&
+    DupOut,           // >&
+    ReadWrite,        // <>
+    Clobber,          // >|
+    HereDoc(HereDoc), // << or <<-, followed by the delimiter word
 }
 
-fn push_str(toks: &mut Vec<Token>, cur: &mut String) {
-    if !cur.is_empty() {
-        toks.push(Token::Str(cur.clone()));
-        cur.clear();
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Token::Word(w) => return write!(f, "{}", w.literal()),
+            Token::IoNumber(n) => return write!(f, "{}", n),
+            Token::Pipe => "|",
+            Token::AndIf => "&&",
+            Token::OrIf => "||",
+            Token::Semi => ";",
+            Token::DSemi => ";;",
+            Token::Background => "&",
+            Token::Newline => "newline",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LeftRedirect => "<",
+            Token::RightRedirect => ">",
+            Token::Append => ">>",
+            Token::DupIn => "<&",
+            Token::DupOut => ">&",
+            Token::ReadWrite => "<>",
+            Token::Clobber => ">|",
+            Token::HereDoc(doc) if doc.strip_tabs => "<<-",
+            Token::HereDoc(_) => "<<",
+        };
+        write!(f, "{}", op)
     }
 }
 
-pub fn lex(line: &String) -> Vec<Token> {
-    // A simple lexer
-    let mut toks = Vec::new();
-    let mut cur = String::new();
-    let mut is_in_str = false;
-    let mut it = line.chars();
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexError {
+    UnterminatedQuote(Quote),
+    UnterminatedHereDoc(String),
+    UnterminatedBrace, // `${` without its `}`
+    TrailingBackslash,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnterminatedQuote(Quote::Single) => write!(f, "unterminated `'`"),
+            LexError::UnterminatedQuote(Quote::AnsiC) => write!(f, "unterminated `$'`"),
+            LexError::UnterminatedQuote(_) => write!(f, "unterminated `\"`"),
+            LexError::UnterminatedHereDoc(delim) => {
+                write!(f, "here-document not terminated by `{}`", delim)
+            }
+            LexError::UnterminatedBrace => write!(f, "unterminated `${{`"),
+            LexError::TrailingBackslash => write!(f, "unexpected end of input after `\\`"),
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// Where the tokens of a line came from, as byte ranges
+#[derive(Debug, Default)]
+pub struct Spans {
+    pub tokens: Vec<(Token, Range<usize>)>,
+    pub quotes: Vec<(Quote, Range<usize>)>, // Quoted strings and here-document bodies
+    pub comments: Vec<Range<usize>>,
+    pub error: Option<LexError>, // Tokens stop where the error happened
+}
+
+// The rest of the line, remembering the byte offset of the next character
+#[derive(Clone)]
+struct Input<'a> {
+    line: &'a str,
+    pos: usize,
+}
+
+impl Iterator for Input<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+}
+
+impl Input<'_> {
+    fn peek(&self) -> Option<char> {
+        self.line[self.pos..].chars().next()
+    }
+
+    fn next_if(&mut self, f: impl FnOnce(char) -> bool) -> Option<char> {
+        self.peek().filter(|&c| f(c))?;
+        self.next()
+    }
+}
+
+struct Lexer<'a> {
+    it: Input<'a>,
+    toks: Vec<Token>,
+    cur: Option<Word>,
+    heredocs: Vec<usize>,      // Here-documents waiting for their bodies
+    ranges: Vec<Range<usize>>, // Where each token came from
+    quotes: Vec<(Quote, Range<usize>)>,
+    comments: Vec<Range<usize>>,
+    start: usize,      // Where the character being looked at starts
+    word_start: usize, // Where `cur` starts
+}
+
+fn heredoc_word(body: &str) -> Word {
+    // Like double quotes, but `"` is not special
+    let mut word = Word::default();
+    word.open(Quote::Double);
+    let mut it = body.chars().peekable();
     while let Some(c) = it.next() {
-        match c {
-            '\n' => {
-                // EOL
-                push_str(&mut toks, &mut cur);
+        if c != '\\' {
+            word.push(c, Quote::Double);
+            continue;
+        }
+        match it.peek() {
+            Some('\n') => {
+                it.next();
             }
-            '\\' => {
-                let cn = it.next().unwrap();
-                if cn != ' ' && cn != '"' {
-                    cur.push(c);
-                }
-                cur.push(cn);
-                continue;
+            Some(&c) if matches!(c, '$' | '`' | '\\') => {
+                it.next();
+                word.push(c, Quote::Escaped);
+            }
+            _ => word.push('\\', Quote::Double),
+        }
+    }
+    word
+}
+
+impl Lexer<'_> {
+    fn word(&mut self) -> &mut Word {
+        if self.cur.is_none() {
+            self.word_start = self.start;
+        }
+        self.cur.get_or_insert_with(Word::default)
+    }
+
+    fn push_tok(&mut self, tok: Token, span: Range<usize>) {
+        self.toks.push(tok);
+        self.ranges.push(span);
+    }
+
+    fn push_word(&mut self) {
+        if let Some(w) = self.cur.take() {
+            self.push_tok(Token::Word(w), self.word_start..self.start);
+        }
+    }
+
+    fn push_op(&mut self, tok: Token) {
+        self.push_word();
+        self.push_tok(tok, self.start..self.it.pos);
+    }
+
+    fn push_redirect(&mut self, tok: Token) {
+        // A number right before the operator is the file descriptor
+        let fd = match self.cur.as_ref().map(|w| &w.0[..]) {
+            Some([part])
+                if part.quote == Quote::Unquoted
+                    && part.text.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                part.text.parse().ok()
             }
-            '"' => {
-                if is_in_str {
-                    is_in_str = false;
-                    // Terminate string
-                    push_str(&mut toks, &mut cur);
+            _ => None,
+        };
+        if let Some(fd) = fd {
+            self.cur = None;
+            self.push_tok(Token::IoNumber(fd), self.word_start..self.start);
+        }
+        self.push_op(tok);
+    }
+
+    fn read_heredocs(&mut self) -> Result<(), LexError> {
+        for idx in std::mem::take(&mut self.heredocs) {
+            let Some(Token::Word(delim)) = self.toks.get(idx + 1) else {
+                // No delimiter, the parser will complain
+                continue;
+            };
+            let quoted = delim.is_quoted();
+            let delim = delim.literal();
+            let Token::HereDoc(doc) = &mut self.toks[idx] else {
+                unreachable!()
+            };
+            let mut body = String::new();
+            let quote = if quoted { Quote::Single } else { Quote::Double };
+            let body_start = self.it.pos;
+            loop {
+                let line_start = self.it.pos;
+                let mut line = String::new();
+                let mut eol = false;
+                for c in self.it.by_ref() {
+                    if c != '\n' {
+                        line.push(c);
+                        continue;
+                    }
+                    let escapes = line.chars().rev().take_while(|&c| c == '\\').count();
+                    if !quoted && escapes % 2 == 1 {
+                        // Line continuation
+                        line.pop();
+                        continue;
+                    }
+                    eol = true;
+                    break;
+                }
+                let line = if doc.strip_tabs {
+                    line.trim_start_matches('\t')
                 } else {
-                    is_in_str = true;
+                    &line
+                };
+                if line == delim {
+                    self.quotes.push((quote, body_start..line_start));
+                    break;
+                }
+                if !eol {
+                    self.quotes.push((quote, body_start..self.it.pos));
+                    let escapes = line.chars().rev().take_while(|&c| c == '\\').count();
+                    if !quoted && escapes % 2 == 1 {
+                        return Err(LexError::TrailingBackslash);
+                    }
+                    return Err(LexError::UnterminatedHereDoc(delim));
+                }
+                body.push_str(line);
+                body.push('\n');
+            }
+            doc.body = if quoted {
+                Word(vec![WordPart { text: body, quote }])
+            } else {
+                heredoc_word(&body)
+            };
+        }
+        Ok(())
+    }
+
+    // Whether `{` comes next, past any line continuations, as after the `$` of `${`
+    fn at_brace(&self) -> bool {
+        let mut rest = &self.it.line[self.it.pos..];
+        while let Some(r) = rest.strip_prefix("\\\n") {
+            rest = r;
+        }
+        rest.starts_with('{')
+    }
+
+    // `${...}` up to its matching `}`, kept as it is for the expander to take apart. Quotes
+    // inside only hide braces, and only `"` counts inside double quotes
+    fn braced(&mut self, quote: Quote) -> Result<(), LexError> {
+        while self.it.next() != Some('{') {}
+        self.word().push('$', quote);
+        self.word().push('{', quote);
+        let mut depth = 1;
+        let mut in_quote = None;
+        let mut prev = '{';
+        while let Some(c) = self.it.next() {
+            match c {
+                '\\' if in_quote != Some('\'') => match self.it.next() {
+                    // Line continuation
+                    Some('\n') => continue,
+                    Some(n) => {
+                        self.word().push('\\', quote);
+                        self.word().push(n, quote);
+                        prev = n;
+                        continue;
+                    }
+                    None => return Err(LexError::TrailingBackslash),
+                },
+                '\'' if in_quote.is_none() && quote == Quote::Double => {}
+                '\'' | '"' if in_quote.is_none() => in_quote = Some(c),
+                c if Some(c) == in_quote => in_quote = None,
+                '{' if in_quote.is_none() && prev == '$' => depth += 1,
+                '}' if in_quote.is_none() => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.word().push('}', quote);
+                        return Ok(());
+                    }
                 }
+                _ => {}
+            }
+            self.word().push(c, quote);
+            prev = c;
+        }
+        Err(match in_quote {
+            Some('\'') => LexError::UnterminatedQuote(Quote::Single),
+            Some(_) => LexError::UnterminatedQuote(Quote::Double),
+            None => LexError::UnterminatedBrace,
+        })
+    }
+
+    fn single_quoted(&mut self) -> Result<(), LexError> {
+        self.word().open(Quote::Single);
+        loop {
+            match self.it.next() {
+                Some('\'') => return Ok(()),
+                Some(c) => self.word().push(c, Quote::Single),
+                None => return Err(LexError::UnterminatedQuote(Quote::Single)),
             }
-            _ if is_in_str => {
-                cur.push(c);
+        }
+    }
+
+    fn double_quoted(&mut self) -> Result<(), LexError> {
+        self.word().open(Quote::Double);
+        loop {
+            match self.it.next() {
+                Some('"') => return Ok(()),
+                Some('\\') => match self.it.peek() {
+                    Some('\n') => {
+                        // Line continuation
+                        self.it.next();
+                    }
+                    Some(c) if matches!(c, '$' | '`' | '"' | '\\') => {
+                        self.it.next();
+                        self.word().push(c, Quote::Escaped);
+                    }
+                    Some(_) => self.word().push('\\', Quote::Double),
+                    None => return Err(LexError::UnterminatedQuote(Quote::Double)),
+                },
+                Some('$') if self.at_brace() => self.braced(Quote::Double)?,
+                Some(c) => self.word().push(c, Quote::Double),
+                None => return Err(LexError::UnterminatedQuote(Quote::Double)),
             }
-            ' ' => {
-                push_str(&mut toks, &mut cur);
+        }
+    }
+
+    // Up to `max` digits, with their value and the text they were written as
+    fn take_digits(&mut self, radix: u32, max: usize) -> Option<(u32, String)> {
+        let mut n = None;
+        let mut text = String::new();
+        for _ in 0..max {
+            match self.it.peek().and_then(|c| c.to_digit(radix)) {
+                Some(d) => {
+                    text.extend(self.it.next());
+                    n = Some(n.unwrap_or(0) * radix + d);
+                }
+                None => break,
             }
-            '|' => {
-                // Pipe
-                push_str(&mut toks, &mut cur);
-                toks.push(Token::Pipe);
+        }
+        n.map(|n| (n, text))
+    }
+
+    fn ansi_c_quoted(&mut self) -> Result<(), LexError> {
+        self.word().open(Quote::AnsiC);
+        let unterminated = LexError::UnterminatedQuote(Quote::AnsiC);
+        loop {
+            let c = match self.it.next() {
+                Some('\'') => return Ok(()),
+                Some('\\') if self.it.peek().is_some_and(|c| c.is_digit(8)) => {
+                    let (n, _) = self.take_digits(8, 3).unwrap_or_default();
+                    char::from_u32(n & 0xff).unwrap_or_default()
+                }
+                Some('\\') => match self.it.next().ok_or(unterminated.clone())? {
+                    'a' => '\x07',
+                    'b' => '\x08',
+                    'e' | 'E' => '\x1b',
+                    'f' => '\x0c',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'v' => '\x0b',
+                    c @ ('\\' | '\'' | '"' | '?') => c,
+                    c @ ('x' | 'u' | 'U') => {
+                        let max = match c {
+                            'x' => 2,
+                            'u' => 4,
+                            _ => 8,
+                        };
+                        match self.take_digits(16, max) {
+                            Some((n, digits)) => match char::from_u32(n) {
+                                Some(c) => c,
+                                None => {
+                                    // Not a code point, kept as written
+                                    for c in ['\\', c].into_iter().chain(digits.chars()) {
+                                        self.word().push(c, Quote::AnsiC);
+                                    }
+                                    continue;
+                                }
+                            },
+                            None => {
+                                self.word().push('\\', Quote::AnsiC);
+                                c
+                            }
+                        }
+                    }
+                    'c' => match self.it.next().ok_or(unterminated.clone())? {
+                        '?' => '\x7f',
+                        x => char::from_u32(x as u32 & 0x1f).unwrap_or_default(),
+                    },
+                    c => {
+                        self.word().push('\\', Quote::AnsiC);
+                        c
+                    }
+                },
+                Some(c) => c,
+                None => return Err(unterminated),
+            };
+            self.word().push(c, Quote::AnsiC);
+        }
+    }
+
+    // The next character of an operator, which may be split by line continuations
+    fn next_op_if(&mut self, f: impl Fn(char) -> bool) -> Option<char> {
+        while self.it.line[self.it.pos..].starts_with("\\\n") {
+            self.it.pos += 2;
+        }
+        self.it.next_if(f)
+    }
+
+    fn run(&mut self) -> Result<(), LexError> {
+        loop {
+            self.start = self.it.pos;
+            let Some(c) = self.it.next() else {
+                break;
+            };
+            match c {
+                '\\' => match self.it.next() {
+                    Some('\n') => {
+                        // Line continuation
+                    }
+                    Some(cn) => self.word().push(cn, Quote::Escaped),
+                    None => return Err(LexError::TrailingBackslash),
+                },
+                '\'' => {
+                    let res = self.single_quoted();
+                    self.quotes.push((Quote::Single, self.start..self.it.pos));
+                    res?;
+                }
+                '"' => {
+                    let res = self.double_quoted();
+                    self.quotes.push((Quote::Double, self.start..self.it.pos));
+                    res?;
+                }
+                '$' if self.at_brace() => self.braced(Quote::Unquoted)?,
+                '$' if self.it.peek() == Some('\'') => {
+                    self.it.next();
+                    let res = self.ansi_c_quoted();
+                    self.quotes.push((Quote::AnsiC, self.start..self.it.pos));
+                    res?;
+                }
+                ' ' | '\t' => {
+                    // Blank
+                    self.push_word();
+                }
+                '\n' => {
+                    // EOL
+                    self.push_op(Token::Newline);
+                    self.read_heredocs()?;
+                }
+                '#' if self.cur.is_none() => {
+                    // Comment
+                    while self.it.next_if(|c| c != '\n').is_some() {}
+                    self.comments.push(self.start..self.it.pos);
+                }
+                '|' if self.next_op_if(|c| c == '|').is_some() => self.push_op(Token::OrIf),
+                '|' => self.push_op(Token::Pipe),
+                '&' if self.next_op_if(|c| c == '&').is_some() => self.push_op(Token::AndIf),
+                '&' => self.push_op(Token::Background),
+                ';' if self.next_op_if(|c| c == ';').is_some() => self.push_op(Token::DSemi),
+                ';' => self.push_op(Token::Semi),
+                '(' => self.push_op(Token::LParen),
+                ')' => self.push_op(Token::RParen),
+                '>' => {
+                    let tok = match self.next_op_if(|c| matches!(c, '>' | '&' | '|')) {
+                        Some('>') => Token::Append,
+                        Some('&') => Token::DupOut,
+                        Some(_) => Token::Clobber,
+                        None => Token::RightRedirect,
+                    };
+                    self.push_redirect(tok);
+                }
+                '<' => {
+                    let tok = match self.next_op_if(|c| matches!(c, '<' | '&' | '>')) {
+                        Some('<') => Token::HereDoc(HereDoc {
+                            strip_tabs: self.next_op_if(|c| c == '-').is_some(),
+                            body: Word::default(),
+                        }),
+                        Some('&') => Token::DupIn,
+                        Some(_) => Token::ReadWrite,
+                        None => Token::LeftRedirect,
+                    };
+                    let is_heredoc = matches!(tok, Token::HereDoc(_));
+                    self.push_redirect(tok);
+                    if is_heredoc {
+                        self.heredocs.push(self.toks.len() - 1);
+                    }
+                }
+                '\0' => {
+                    // EOF
+                    break;
+                }
+                _ => self.word().push(c, Quote::Unquoted),
             }
-            '>' => {
-                // R-Redirect
-                push_str(&mut toks, &mut cur);
-                toks.push(Token::RightRedirect);
+        }
+        self.push_word();
+        if let Some(&idx) = self.heredocs.first() {
+            if let Some(Token::Word(delim)) = self.toks.get(idx + 1) {
+                return Err(LexError::UnterminatedHereDoc(delim.literal()));
             }
-            '<' => {
-                // L-Redirect
-                push_str(&mut toks, &mut cur);
-                toks.push(Token::LeftRedirect);
+        }
+        Ok(())
+    }
+}
+
+fn lexer(line: &str) -> Lexer<'_> {
+    Lexer {
+        it: Input { line, pos: 0 },
+        toks: Vec::new(),
+        cur: None,
+        heredocs: Vec::new(),
+        ranges: Vec::new(),
+        quotes: Vec::new(),
+        comments: Vec::new(),
+        start: 0,
+        word_start: 0,
+    }
+}
+
+pub fn lex(line: &str) -> Result<Vec<Token>, LexError> {
+    // A simple lexer
+    let mut lexer = lexer(line);
+    lexer.run()?;
+    Ok(lexer.toks)
+}
+
+/// Words with a meaning of their own in command position
+pub const RESERVED: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "do", "done", "case", "esac", "while", "until", "for",
+    "in", "{", "}", "!", "time",
+];
+
+pub fn is_name(s: &str) -> bool {
+    let mut it = s.chars();
+    matches!(it.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && it.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The name and value of an assignment word like `NAME=value`, where `NAME=` is unquoted
+pub fn assignment(w: &Word) -> Option<(String, Word)> {
+    let first = w.0.first().filter(|p| p.quote == Quote::Unquoted)?;
+    let (name, rest) = first.text.split_once('=')?;
+    if !is_name(name) {
+        return None;
+    }
+    let mut value = Word(w.0[1..].to_vec());
+    if !rest.is_empty() {
+        value.0.insert(
+            0,
+            WordPart {
+                text: rest.to_string(),
+                quote: Quote::Unquoted,
+            },
+        );
+    }
+    Some((name.to_string(), value))
+}
+
+/// What a word is for, judging from the tokens before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Command,
+    Keyword,
+    Redirect, // The target of a redirection
+    Argument,
+}
+
+/// The role of each token, `None` for operators
+pub fn roles<'a>(toks: impl IntoIterator<Item = &'a Token>) -> Vec<Option<Role>> {
+    let mut expect_cmd = true;
+    let mut redirect_target = false;
+    let mut res = Vec::new();
+    for tok in toks {
+        let role = match tok {
+            Token::Word(_) if redirect_target => {
+                redirect_target = false;
+                Some(Role::Redirect)
             }
-            '&' => {
-                // Background
-                push_str(&mut toks, &mut cur);
-                toks.push(Token::Background);
+            Token::Word(w) if expect_cmd => {
+                let name = w.literal();
+                if !w.is_quoted() && RESERVED.contains(&name.as_str()) {
+                    // `for` and `case` are followed by a name or a word, not a command
+                    expect_cmd = !matches!(name.as_str(), "for" | "case" | "fi" | "done" | "esac");
+                    Some(Role::Keyword)
+                } else if assignment(w).is_some() {
+                    // The command is still to come
+                    Some(Role::Argument)
+                } else {
+                    expect_cmd = false;
+                    Some(Role::Command)
+                }
             }
-            '\0' => {
-                // EOF
-                push_str(&mut toks, &mut cur);
-                break;
+            Token::Word(_) => Some(Role::Argument),
+            Token::IoNumber(_) => None,
+            Token::LeftRedirect
+            | Token::RightRedirect
+            | Token::Append
+            | Token::DupIn
+            | Token::DupOut
+            | Token::ReadWrite
+            | Token::Clobber
+            | Token::HereDoc(_) => {
+                redirect_target = true;
+                None
             }
             _ => {
-                cur.push(c);
+                // Separators and pipes start a new command
+                expect_cmd = true;
+                redirect_target = false;
+                None
             }
-        }
+        };
+        res.push(role);
+    }
+    res
+}
+
+/// Lex as far as possible, keeping where everything came from
+pub fn lex_spans(line: &str) -> Spans {
+    let mut lexer = lexer(line);
+    let error = lexer.run().err();
+    Spans {
+        tokens: lexer.toks.into_iter().zip(lexer.ranges).collect(),
+        quotes: lexer.quotes,
+        comments: lexer.comments,
+        error,
     }
-    push_str(&mut toks, &mut cur);
-    toks
 }
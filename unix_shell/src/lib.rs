@@ -1,3 +1,11 @@
 pub mod ast;
+pub mod builtin;
+pub mod complete;
 pub mod eval;
+pub mod expand;
+pub mod glob;
+pub mod highlight;
+pub mod job;
 pub mod lex;
+pub mod shell;
+pub mod trap;
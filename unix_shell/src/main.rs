@@ -2,45 +2,93 @@ use nix::sys::wait::wait;
 use std::borrow::Cow::{self, Borrowed, Owned};
 use std::env::current_dir;
 
-use rustyline::completion::FilenameCompleter;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
 use rustyline::error::ReadlineError;
-use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
+use rustyline::highlight::Highlighter;
 use rustyline::hint::HistoryHinter;
-use rustyline::validate::MatchingBracketValidator;
-use rustyline::{Completer, Config, Helper, Hinter, Validator};
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Config, Context, Editor, Helper, Hinter};
+use std::cell::RefCell;
 use std::process::exit;
-use unix_shell::ast::parse;
-use unix_shell::eval::{check_prog, eval, Input, Interrupt, Output};
-use unix_shell::lex::lex;
-
-fn execute(line: &String) -> Result<(), Interrupt> {
-    let args = lex(line);
-    // println!("{:?}", args); // Print the lexer result
-    let len = args.len();
-    if len == 0 {
-        return Ok(());
-    }
-    if let Some(ast) = parse(args) {
-        // println!("{:?}", ast); // Print the AST
-        check_prog(&ast)?;
-        eval(&ast, &Input::Stdin, &Output::Stdout, false)
-    } else {
-        Err(Interrupt::ExecError(format!("Syntax error")))
+use std::rc::Rc;
+use unix_shell::ast::is_incomplete;
+use unix_shell::builtin;
+use unix_shell::complete::complete;
+use unix_shell::eval::{execute, Interrupt};
+use unix_shell::highlight::{highlight, in_path, Theme};
+use unix_shell::shell::Shell;
+use unix_shell::trap;
+
+// Runs a line, returning the exit code if the shell should quit
+fn run(shell: &mut Shell, line: &str) -> Option<i32> {
+    match execute(shell, line) {
+        Ok(_) => None,
+        Err(Interrupt::ChildError(e)) => {
+            eprintln!("Sub-process Error: {}", e);
+            Some(1)
+        }
+        Err(Interrupt::Exit(code)) => Some(code),
+        Err(Interrupt::ExecError(e)) => {
+            eprintln!("Error: {}", e);
+            shell.status = 1;
+            None
+        }
+        // `return` only gets here from where it is an error
+        Err(Interrupt::Return(status)) => {
+            shell.status = status;
+            None
+        }
     }
 }
 
-#[derive(Helper, Completer, Hinter, Validator)]
+#[derive(Helper, Hinter)]
 struct MyHelper {
-    #[rustyline(Completer)]
     completer: FilenameCompleter,
-    highlighter: MatchingBracketHighlighter,
-    #[rustyline(Validator)]
-    validator: MatchingBracketValidator,
+    shell: Rc<RefCell<Shell>>, // Shared with the main loop
+    theme: Theme,
     #[rustyline(Hinter)]
     hinter: HistoryHinter,
     colored_prompt: String,
 }
 
+impl Completer for MyHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        match complete(&mut self.shell.borrow_mut(), line, pos) {
+            Ok(Some((start, candidates))) => {
+                let pairs = candidates
+                    .into_iter()
+                    .map(|c| Pair {
+                        display: c.clone(),
+                        replacement: c,
+                    })
+                    .collect();
+                Ok((start, pairs))
+            }
+            // File names
+            Ok(None) => self.completer.complete(line, pos, ctx),
+            Err(Interrupt::ExecError(e)) => {
+                eprintln!("\nError: {}", e);
+                Ok((pos, Vec::new()))
+            }
+            // A child started by a completion command, or `exit` in a completion function
+            Err(Interrupt::ChildError(e)) => {
+                eprintln!("Sub-process Error: {}", e);
+                exit(1)
+            }
+            Err(Interrupt::Exit(code)) => exit(code),
+            Err(Interrupt::Return(_)) => Ok((pos, Vec::new())),
+        }
+    }
+}
+
 impl Highlighter for MyHelper {
     fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
         &'s self,
@@ -58,28 +106,84 @@ impl Highlighter for MyHelper {
         Owned("\x1b[38;5;244m".to_owned() + hint + "\x1b[m")
     }
 
-    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
-        self.highlighter.highlight(line, pos)
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let shell = self.shell.borrow();
+        Owned(highlight(line, &self.theme, |name| {
+            builtin::find(name).is_some() || shell.functions.contains_key(name) || in_path(name)
+        }))
     }
 
-    fn highlight_char(&self, line: &str, pos: usize, forced: bool) -> bool {
-        self.highlighter.highlight_char(line, pos, forced)
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        // Any edit can change the colour of the whole line
+        true
     }
 }
 
+// Every line is accepted, `read_command` asks for the rest of an unfinished command with PS2
+impl Validator for MyHelper {}
+
+// Report the background jobs that finished since the last prompt
+fn notify_jobs(shell: &mut Shell) {
+    shell.jobs.reap();
+    for (job, mark) in shell.jobs.take_done() {
+        println!("[{}]{}  {:<24}{}", job.id, mark, job.state(), job.text);
+    }
+}
+
+// Read a whole command, asking for more lines with PS2 while it is incomplete
+fn read_command(
+    rl: &mut Editor<MyHelper, DefaultHistory>,
+    prompt: &str,
+) -> rustyline::Result<String> {
+    let mut line = rl.readline(prompt)?;
+    while is_incomplete(&line) {
+        let helper = rl.helper_mut().expect("No helper");
+        let ps2 = helper.shell.borrow().var("PS2").unwrap_or("> ").to_string();
+        helper.colored_prompt = ps2.clone();
+        match rl.readline(&ps2) {
+            Ok(next) => {
+                line.push('\n');
+                line.push_str(&next);
+            }
+            // Let the parser report what is missing
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(line)
+}
+
 fn main() {
+    let mut shell = Shell::new();
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, flag, cmd, rest @ ..] = &args[..] {
+        if flag == "-c" {
+            // unix_shell -c command [name [args...]]
+            if let Some((name, args)) = rest.split_first() {
+                shell.name = name.clone();
+                shell.args = args.to_vec();
+            }
+            let exit_code = run(&mut shell, cmd).unwrap_or(shell.status);
+            let exit_code = trap::run_exit(&mut shell, exit_code);
+            while wait().is_ok() {}
+            exit(exit_code);
+        }
+    }
+
     let config = Config::builder()
         .check_cursor_position(true)
         .completion_type(rustyline::CompletionType::List)
         .edit_mode(rustyline::EditMode::Emacs)
         .build();
 
+    shell.interactive = true;
+    let shell = Rc::new(RefCell::new(shell));
     let h = MyHelper {
         completer: FilenameCompleter::new(),
-        highlighter: MatchingBracketHighlighter::new(),
+        shell: shell.clone(),
+        theme: Theme::parse(&std::env::var("HIGHLIGHT_COLORS").unwrap_or_default()),
         hinter: HistoryHinter::new(),
         colored_prompt: "".to_owned(),
-        validator: MatchingBracketValidator::new(),
     };
     let mut exit_code = 0;
 
@@ -87,11 +191,12 @@ fn main() {
     rl.set_helper(Some(h));
     let _ = rl.load_history("/tmp/.history");
     loop {
+        notify_jobs(&mut shell.borrow_mut());
         let fname = current_dir().unwrap();
         let fname = fname.file_name().unwrap_or_default().to_str().unwrap();
         let p = format!("{}> ", fname);
         rl.helper_mut().expect("No helper").colored_prompt = format!("\x1b[1;32m{p}\x1b[0m");
-        let readline = rl.readline(&p);
+        let readline = read_command(&mut rl, &p);
         let line = match readline {
             Ok(l) => {
                 rl.add_history_entry(l.as_str()).unwrap();
@@ -109,24 +214,13 @@ fn main() {
                 break;
             }
         };
-        if let Err(e) = execute(&line) {
-            match e {
-                Interrupt::ChildError(e) => {
-                    eprintln!("Sub-process Error: {}", e);
-                    exit_code = 1;
-                    break;
-                }
-                Interrupt::Exit(code) => {
-                    exit_code = code;
-                    break;
-                }
-                Interrupt::ExecError(e) => {
-                    eprintln!("Error: {}", e);
-                }
-            }
+        if let Some(code) = run(&mut shell.borrow_mut(), &line) {
+            exit_code = code;
+            break;
         }
     }
+    let exit_code = trap::run_exit(&mut shell.borrow_mut(), exit_code);
     // println!("[DEBUG] Wait for all child processes to quit...");
-    while let Ok(_) = wait() {}
+    while wait().is_ok() {}
     exit(exit_code);
 }
@@ -0,0 +1,283 @@
+use crate::ast::Command;
+use crate::complete::Spec;
+use crate::highlight::is_executable;
+use crate::job::Jobs;
+use crate::trap::Condition;
+use nix::unistd::{getcwd, getpid, Pid};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Var {
+    pub value: Value,
+    pub exported: bool, // Arrays are never exported
+}
+
+/// Every option, with the letter of its short form like `set -e`
+pub const OPTIONS: &[(&str, Option<char>)] = &[
+    ("errexit", Some('e')),
+    ("nounset", Some('u')),
+    ("pipefail", None),
+    ("xtrace", Some('x')),
+];
+
+/// Options changed with `set -o name` and `set +o name`
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub errexit: bool,  // Exit when a command fails, outside of conditions
+    pub nounset: bool,  // Expanding an unset variable is an error
+    pub pipefail: bool, // A pipeline fails when any of its stages fails
+    pub xtrace: bool,   // Print each command with `PS4` before running it
+}
+
+impl Options {
+    /// The flag of an option in `OPTIONS`
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "nounset" => Some(&mut self.nounset),
+            "pipefail" => Some(&mut self.pipefail),
+            "xtrace" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "errexit" => Some(self.errexit),
+            "nounset" => Some(self.nounset),
+            "pipefail" => Some(self.pipefail),
+            "xtrace" => Some(self.xtrace),
+            _ => None,
+        }
+    }
+
+    /// The option with a short form `letter`
+    pub fn by_letter(letter: char) -> Option<&'static str> {
+        OPTIONS
+            .iter()
+            .find(|(_, l)| *l == Some(letter))
+            .map(|(name, _)| *name)
+    }
+
+    /// The letters of the options that are on, for `$-`
+    pub fn letters(&self) -> String {
+        OPTIONS
+            .iter()
+            .filter(|(name, _)| self.get(name) == Some(true))
+            .filter_map(|(_, letter)| *letter)
+            .collect()
+    }
+}
+
+/// Everything the shell keeps between commands
+pub struct Shell {
+    pub vars: HashMap<String, Var>,
+    pub name: String,      // $0
+    pub args: Vec<String>, // $1, $2, ...
+    pub status: i32,       // $?
+    pub pid: Pid,          // $$, the same in subshells
+    pub functions: HashMap<String, Rc<Command>>,
+    pub completions: HashMap<String, Spec>, // Set by `complete`
+    pub jobs: Jobs,
+    pub last_bg: Option<Pid>, // $!
+    pub interactive: bool,    // Reading commands from the prompt
+    pub options: Options,
+    pub in_condition: bool, // `errexit` is ignored, as in the condition of an `if`
+    pub frames: usize,      // Functions and sourced files being run, which `return` can end
+    pub dirs: Vec<String>,  // The stack of `pushd`, below the working directory
+    pub traps: BTreeMap<Condition, String>, // Set by `trap`, an empty action ignores
+    pub in_trap: bool,      // A trap action is running, and no other starts
+    pub hashed: HashMap<String, (String, usize)>, // Commands found in `PATH`, with their hits
+    pub waited: [Duration; 2], // User and system time of the children waited for in the foreground
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        // Inherit the environment as exported variables
+        let vars = env::vars()
+            .map(|(k, v)| {
+                (
+                    k,
+                    Var {
+                        value: Value::Scalar(v),
+                        exported: true,
+                    },
+                )
+            })
+            .collect();
+        let mut shell = Shell {
+            vars,
+            name: env::args().next().unwrap_or_else(|| "unix_shell".into()),
+            args: Vec::new(),
+            status: 0,
+            pid: getpid(),
+            functions: HashMap::new(),
+            completions: HashMap::new(),
+            jobs: Jobs::default(),
+            last_bg: None,
+            interactive: false,
+            options: Options::default(),
+            in_condition: false,
+            frames: 0,
+            dirs: Vec::new(),
+            traps: BTreeMap::new(),
+            in_trap: false,
+            hashed: HashMap::new(),
+            waited: [Duration::ZERO; 2],
+        };
+        // PWD is always set, and right
+        if let Ok(cwd) = shell.cwd() {
+            shell.set_var("PWD", cwd);
+            shell.export("PWD");
+        }
+        shell
+    }
+
+    /// The working directory as `PWD` has it, through the symlinks `cd` took, as long as it
+    /// is still right. Otherwise the physical path
+    pub fn cwd(&self) -> nix::Result<String> {
+        let cwd = getcwd()?;
+        let same = |path: &str| match (fs::metadata(path), fs::metadata(&cwd)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        };
+        match self.var("PWD") {
+            Some(pwd)
+                if pwd.starts_with('/')
+                    && !pwd.split('/').any(|c| c == "." || c == "..")
+                    && same(pwd) =>
+            {
+                Ok(pwd.to_string())
+            }
+            _ => Ok(cwd.to_string_lossy().into_owned()),
+        }
+    }
+
+    /// A scalar, or the first element of an array
+    pub fn var(&self, name: &str) -> Option<&str> {
+        match &self.vars.get(name)?.value {
+            Value::Scalar(s) => Some(s),
+            Value::Array(a) => a.first().map(String::as_str),
+        }
+    }
+
+    /// The elements of an array, a scalar is an array of one
+    pub fn array(&self, name: &str) -> Option<&[String]> {
+        match &self.vars.get(name)?.value {
+            Value::Scalar(s) => Some(std::slice::from_ref(s)),
+            Value::Array(a) => Some(a),
+        }
+    }
+
+    fn set_value(&mut self, name: &str, value: Value) {
+        if name == "PATH" {
+            self.hashed.clear();
+        }
+        match self.vars.get_mut(name) {
+            Some(v) => v.value = value,
+            None => {
+                self.vars.insert(
+                    name.to_string(),
+                    Var {
+                        value,
+                        exported: false,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn set_var(&mut self, name: &str, value: String) {
+        self.set_value(name, Value::Scalar(value));
+    }
+
+    pub fn set_array(&mut self, name: &str, values: Vec<String>) {
+        self.set_value(name, Value::Array(values));
+    }
+
+    /// Put a variable back as it was before, `None` when it was unset
+    pub fn restore_var(&mut self, name: &str, var: Option<Var>) {
+        if name == "PATH" {
+            self.hashed.clear();
+        }
+        match var {
+            Some(var) => self.vars.insert(name.to_string(), var),
+            None => self.vars.remove(name),
+        };
+    }
+
+    pub fn export(&mut self, name: &str) {
+        if let Some(v) = self.vars.get_mut(name) {
+            v.exported = true;
+        }
+    }
+
+    pub fn unset_var(&mut self, name: &str) {
+        if name == "PATH" {
+            self.hashed.clear();
+        }
+        self.vars.remove(name);
+    }
+
+    /// Every executable called `name` in the directories of `PATH`, in order
+    pub fn search_path(&self, name: &str) -> Vec<String> {
+        if name.is_empty() || name.contains('/') {
+            return Vec::new();
+        }
+        // An empty entry is the working directory
+        let path = self.var("PATH").unwrap_or("/usr/local/bin:/usr/bin:/bin");
+        path.split(':')
+            .map(|dir| Path::new(if dir.is_empty() { "." } else { dir }).join(name))
+            .filter(|file| is_executable(file))
+            .map(|file| file.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// The program to run for the command `name`, from the hash table while it is still
+    /// there, and remembered in it otherwise
+    pub fn find_command(&mut self, name: &str) -> Option<String> {
+        if name.contains('/') {
+            return Some(name.to_string());
+        }
+        match self.hashed.get_mut(name) {
+            Some((path, hits)) if is_executable(Path::new(path.as_str())) => {
+                *hits += 1;
+                return Some(path.clone());
+            }
+            _ => {}
+        }
+        let path = self.search_path(name).into_iter().next()?;
+        self.hashed.insert(name.to_string(), (path.clone(), 1));
+        Some(path)
+    }
+
+    /// The environment given to child processes, as `NAME=value` strings
+    pub fn envp(&self) -> Vec<CString> {
+        self.vars
+            .iter()
+            .filter_map(|(k, v)| match &v.value {
+                Value::Scalar(s) if v.exported => CString::new(format!("{}={}", k, s)).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,158 @@
+use crate::eval::{execute, Interrupt};
+use crate::shell::Shell;
+use nix::libc::c_int;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// What a trap can be set on: a signal, or one of the shell's own events
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Condition {
+    Exit, // The shell exits
+    Signal(Signal),
+    Debug,  // Before each simple command
+    Err,    // A command fails, where `errexit` would exit
+    Return, // A function or sourced file ends
+}
+
+impl Condition {
+    /// A name like `EXIT`, `INT`, `SIGINT` or `int`, or a signal number (0 is `EXIT`)
+    pub fn parse(spec: &str) -> Option<Condition> {
+        if let Ok(n) = spec.parse::<i32>() {
+            return match n {
+                0 => Some(Condition::Exit),
+                n => Signal::try_from(n).ok().map(Condition::Signal),
+            };
+        }
+        let name = spec.to_ascii_uppercase();
+        match name.as_str() {
+            "EXIT" => Some(Condition::Exit),
+            "DEBUG" => Some(Condition::Debug),
+            "ERR" => Some(Condition::Err),
+            "RETURN" => Some(Condition::Return),
+            _ if name.starts_with("SIG") => Signal::from_str(&name).ok().map(Condition::Signal),
+            _ => Signal::from_str(&format!("SIG{}", name))
+                .ok()
+                .map(Condition::Signal),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Condition::Exit => "EXIT",
+            Condition::Signal(sig) => sig.as_str(),
+            Condition::Debug => "DEBUG",
+            Condition::Err => "ERR",
+            Condition::Return => "RETURN",
+        }
+    }
+
+    // The order `trap -p` lists them in
+    fn rank(&self) -> i32 {
+        match self {
+            Condition::Exit => 0,
+            Condition::Signal(sig) => *sig as i32,
+            Condition::Debug => 100,
+            Condition::Err => 101,
+            Condition::Return => 102,
+        }
+    }
+}
+
+impl PartialOrd for Condition {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Condition {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+// Signals caught since the last safe point, by number
+static PENDING: [AtomicBool; 32] = [const { AtomicBool::new(false) }; 32];
+
+extern "C" fn on_signal(n: c_int) {
+    // Only flag it, the action runs later from `run_pending`
+    if let Some(flag) = PENDING.get(n as usize) {
+        flag.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Catch `sig` for a trap, ignore it for an empty action, or restore the default for `None`
+pub fn set_handler(sig: Signal, action: Option<&str>) -> nix::Result<()> {
+    let handler = match action {
+        Some("") => SigHandler::SigIgn,
+        Some(_) => SigHandler::Handler(on_signal),
+        None => SigHandler::SigDfl,
+    };
+    // Restarted, so that waiting for a foreground command goes on and the trap runs after it
+    let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+    // Safety: the handler only stores to an atomic
+    unsafe { sigaction(sig, &action) }.map(|_| ())
+}
+
+/// Run the action for `cond`, if there is one and no other trap is running. `$?` is kept
+pub fn run(shell: &mut Shell, cond: Condition) -> Result<(), Interrupt> {
+    if shell.in_trap {
+        return Ok(());
+    }
+    let Some(action) = shell.traps.get(&cond).filter(|a| !a.is_empty()).cloned() else {
+        return Ok(());
+    };
+    let status = shell.status;
+    shell.in_trap = true;
+    let res = execute(shell, &action);
+    shell.in_trap = false;
+    shell.status = status;
+    match res {
+        Err(Interrupt::ExecError(e)) => {
+            eprintln!("Error: {}", e);
+            Ok(())
+        }
+        Err(e) => Err(e),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Run the traps of the signals caught since the last call
+pub fn run_pending(shell: &mut Shell) -> Result<(), Interrupt> {
+    for sig in Signal::iterator() {
+        let caught = PENDING
+            .get(sig as usize)
+            .is_some_and(|flag| flag.swap(false, Ordering::SeqCst));
+        if caught {
+            run(shell, Condition::Signal(sig))?;
+        }
+    }
+    Ok(())
+}
+
+/// Run the `EXIT` trap as the shell ends with `code`, which the trap may change with `exit`
+pub fn run_exit(shell: &mut Shell, code: i32) -> i32 {
+    shell.status = code;
+    let res = run(shell, Condition::Exit);
+    shell.traps.remove(&Condition::Exit);
+    match res {
+        Err(Interrupt::Exit(code)) => code,
+        _ => code,
+    }
+}
+
+/// In a forked child, traps go back to the default, but ignored signals stay ignored
+pub fn reset(shell: &mut Shell) {
+    shell.traps.retain(|cond, action| {
+        if action.is_empty() {
+            return true;
+        }
+        if let Condition::Signal(sig) = cond {
+            let _ = set_handler(*sig, None);
+        }
+        false
+    });
+    for flag in &PENDING {
+        flag.store(false, Ordering::SeqCst);
+    }
+}
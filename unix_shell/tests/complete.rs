@@ -0,0 +1,85 @@
+use unix_shell::ast::parse;
+use unix_shell::complete::{commands, complete, cursor, Context, Cursor};
+use unix_shell::eval::eval;
+use unix_shell::lex::lex;
+use unix_shell::shell::Shell;
+
+fn context(line: &str) -> (Context, usize) {
+    let Cursor { context, start, .. } = cursor(line, line.len());
+    (context, start)
+}
+
+#[test]
+fn contexts() {
+    assert_eq!(context(""), (Context::Command, 0));
+    assert_eq!(context("ca"), (Context::Command, 0));
+    assert_eq!(context("ls -l sr"), (Context::Argument, 6));
+    assert_eq!(context("ls -l "), (Context::Argument, 6));
+    assert_eq!(context("ls | gr"), (Context::Command, 5));
+    assert_eq!(context("true && "), (Context::Command, 8));
+    assert_eq!(context("if tr"), (Context::Command, 3));
+    assert_eq!(context("for x"), (Context::Argument, 4));
+    assert_eq!(context("cat > ou"), (Context::Redirect, 6));
+    assert_eq!(context("cat 2>"), (Context::Redirect, 6));
+    assert_eq!(context("> out ec"), (Context::Command, 6));
+    assert_eq!(context("echo $HO"), (Context::Variable, 6));
+    assert_eq!(context("echo \"${HO"), (Context::Variable, 8));
+    assert_eq!(context("echo '$HO"), (Context::Argument, 5));
+    assert_eq!(context("echo \"a b"), (Context::Argument, 5));
+}
+
+#[test]
+fn candidates() {
+    let mut shell = Shell::new();
+    shell.set_var("PATH", "/bin:/usr/bin".into());
+    shell.set_var("COMPLETE_ME", "1".into());
+    let found = commands(&shell, "ls");
+    assert!(found.contains(&"ls".to_string()));
+    assert!(found.iter().all(|c| c.starts_with("ls")));
+    assert!(commands(&shell, "ex").contains(&"exit".to_string()));
+    assert!(commands(&shell, "whi").contains(&"while".to_string()));
+
+    assert_eq!(
+        complete(&mut shell, "echo $COMPLETE_", 15).unwrap(),
+        Some((6, vec!["COMPLETE_ME".to_string()]))
+    );
+    // File names are left to the filename completer
+    assert_eq!(complete(&mut shell, "cat sr", 6).unwrap(), None);
+    assert_eq!(complete(&mut shell, "./tar", 5).unwrap(), None);
+}
+
+fn run(shell: &mut Shell, script: &str) {
+    eval(shell, &parse(lex(script).unwrap()).unwrap()).unwrap();
+}
+
+fn candidates_at(shell: &mut Shell, line: &str) -> Vec<String> {
+    let (_, candidates) = complete(shell, line, line.len()).unwrap().unwrap();
+    candidates
+}
+
+#[test]
+fn programmable() {
+    assert_eq!(cursor("git com", 7).words, ["git", "com"]);
+    assert_eq!(cursor("a; git 'x y' ", 13).words, ["git", "'x y'", ""]);
+
+    let mut shell = Shell::new();
+    run(&mut shell, "complete -W 'build check clean' cargo");
+    assert_eq!(candidates_at(&mut shell, "cargo c"), ["check", "clean"]);
+    assert_eq!(
+        candidates_at(&mut shell, "cargo "),
+        ["build", "check", "clean"]
+    );
+    // Files as usual for commands without a spec
+    assert_eq!(complete(&mut shell, "cat c", 5).unwrap(), None);
+
+    // The function sees the command line through COMP_WORDS and COMP_CWORD
+    run(&mut shell, "_f() { true; }; complete -F _f f");
+    assert!(candidates_at(&mut shell, "f a b").is_empty());
+    assert_eq!(shell.array("COMP_WORDS").unwrap(), ["f", "a", "b"]);
+    assert_eq!(shell.var("COMP_CWORD"), Some("2"));
+
+    run(&mut shell, r#"complete -C "printf '%s\n'" p"#);
+    assert_eq!(candidates_at(&mut shell, "p x y"), ["p", "y", "x"]);
+    run(&mut shell, "complete -r p");
+    assert_eq!(complete(&mut shell, "p x", 3).unwrap(), None);
+}
@@ -0,0 +1,739 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn sh(script: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_unix_shell"))
+        .args(["-c", script])
+        .output()
+        .unwrap()
+}
+
+fn stdout(script: &str) -> String {
+    String::from_utf8(sh(script).stdout).unwrap()
+}
+
+// Feed a script on stdin, one line at a time like an interactive session
+fn stdin(script: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_unix_shell"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap()
+}
+
+#[test]
+fn lists() {
+    assert_eq!(stdout("true && echo a || echo b"), "a\n");
+    assert_eq!(stdout("false && echo a || echo b"), "b\n");
+    assert_eq!(stdout("! true; echo $?"), "1\n");
+    assert_eq!(stdout("echo a | tr a b; echo c"), "b\nc\n");
+    assert_eq!(
+        stdout("sleep 0.1 && echo late & echo early; wait"),
+        "early\nlate\n"
+    );
+    assert_eq!(sh("false").status.code(), Some(1));
+    assert_eq!(sh("exit 3").status.code(), Some(3));
+}
+
+#[test]
+fn compound() {
+    assert_eq!(
+        stdout("if false; then echo a; elif true; then echo b; else echo c; fi"),
+        "b\n"
+    );
+    assert_eq!(stdout("for i in 1 2 3; do echo $i; done"), "1\n2\n3\n");
+    assert_eq!(
+        stdout("for i in a b\ndo\n  echo $i\ndone | tr a-z A-Z"),
+        "A\nB\n"
+    );
+    assert_eq!(
+        stdout("case foo.rs in *.c) echo c;; *.rs|*.toml) echo rust;; esac"),
+        "rust\n"
+    );
+    assert_eq!(
+        stdout("case '*' in \\*) echo star;; *) echo other;; esac"),
+        "star\n"
+    );
+    assert_eq!(stdout("while false; do echo never; done; echo $?"), "0\n");
+    assert_eq!(
+        stdout("until true; do echo never; done; echo done"),
+        "done\n"
+    );
+    assert_eq!(stdout("{ echo a; echo b; } | wc -l"), "2\n");
+    assert_eq!(stdout("(cd /; pwd); pwd | grep -c '^/$'"), "/\n0\n");
+    assert_eq!(stdout("f() { echo \"$1-$2\"; }; f a b"), "a-b\n");
+}
+
+#[test]
+fn field_splitting() {
+    assert_eq!(stdout("x=' a  b '; set -- $x; echo $#"), "2\n");
+    // Other IFS characters each end a field, empty ones too
+    assert_eq!(
+        stdout("IFS=:; x=a::b:; set -- $x; echo $#; for f in $x; do echo \"<$f>\"; done"),
+        "3\n<a>\n<>\n<b>\n"
+    );
+    assert_eq!(
+        stdout("IFS=' :'; x=' :a : b :: c'; set -- $x; echo $# \"$1|$2|$3|$4|$5\""),
+        "5 |a|b||c\n"
+    );
+}
+
+#[test]
+fn redirects() {
+    assert_eq!(stdout("ls /nonexistent 2>&1 | wc -l"), "1\n");
+    assert_eq!(stdout("cat <<EOF\nargs: $#\nEOF"), "args: 0\n");
+    assert_eq!(stdout("cat <<'EOF'\n$HOME\nEOF"), "$HOME\n");
+    assert_eq!(stdout("cat <<-EOF\n\tindented\n\tEOF"), "indented\n");
+    assert_eq!(stdout("{ echo out; echo err >&2; } 2>/dev/null"), "out\n");
+    // Here-documents are written in TMPDIR
+    assert_eq!(
+        stdout("TMPDIR=/nonexistent; cat <<EOF 2>&1\nx\nEOF\nTMPDIR=/tmp; cat <<EOF\ny\nEOF"),
+        "y\n"
+    );
+}
+
+#[test]
+fn complete_specs() {
+    assert_eq!(
+        stdout("complete -W 'a b' -C 'x y' c d; complete -F f e; complete -r d; complete -p"),
+        "complete -W 'a b' -C 'x y' c\ncomplete -F f e\n"
+    );
+    assert_eq!(sh("complete -W").status.code(), Some(1));
+}
+
+#[test]
+fn multiline() {
+    assert_eq!(
+        stdin("if true\nthen echo yes\nfi\necho \"a\nb\" |\ntr a x\ncat <<E\nhi\nE\necho one \\\ntwo\n"),
+        "yes\nx\nb\nhi\none two\n"
+    );
+    // A dumb terminal still gets the prompts, PS2 for each line of an unfinished command
+    let mut child = Command::new(env!("CARGO_BIN_EXE_unix_shell"))
+        .env("TERM", "dumb")
+        .env("PS2", "more> ")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"if true\nthen echo yes\nfi\necho 'a\nb'\n")
+        .unwrap();
+    let out = String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap();
+    assert!(
+        out.contains("> more> more> yes\n") && out.contains("> more> a\nb\n"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn background_jobs() {
+    // Notices come before the next prompt, once the job is reaped
+    assert_eq!(
+        stdin("sleep 0.1 && echo bg &\nsleep 0.3\nfalse &\nsleep 0.1\necho end\n"),
+        "bg\n[1]+  Done                    sleep 0.1 && echo bg\n[1]+  Exit 1                  false\nend\n"
+    );
+    assert_eq!(stdout("sleep 0.1 & test -n \"$!\" && echo pid"), "pid\n");
+    // A background job finishing does not end a foreground wait early
+    assert_eq!(stdout("true & sleep 0.2; echo done"), "done\n");
+}
+
+#[test]
+fn wait_builtin() {
+    assert_eq!(stdout("(exit 3) & wait $!; echo $?"), "3\n");
+    assert_eq!(stdout("(exit 4) & sleep 0.1; wait %1; echo $?"), "4\n");
+    assert_eq!(stdout("(exit 2) & true & wait %-; echo $?"), "2\n");
+    assert_eq!(stdout("sleep 0.1 & (exit 5) & wait %sleep; echo $?"), "0\n");
+    assert_eq!(stdout("false & wait; echo $?"), "0\n");
+    assert_eq!(stdout("wait %1; echo $?; wait 1; echo $?"), "127\n127\n");
+    // With SIGCHLD ignored the kernel reaps it, and its status is unknown
+    assert_eq!(stdout("trap '' CHLD; (exit 0) & wait %1; echo $?"), "127\n");
+}
+
+#[test]
+fn kill_builtin() {
+    // The whole group goes, or the sleeps would keep stdout open for seconds
+    let start = std::time::Instant::now();
+    assert_eq!(
+        stdout("sleep 5 | sleep 5 & kill %1; wait %1; echo $?"),
+        "143\n"
+    );
+    assert!(start.elapsed() < std::time::Duration::from_secs(4));
+    assert_eq!(stdout("sleep 5 & kill -9 $!; wait $!; echo $?"), "137\n");
+    assert_eq!(
+        stdout("sleep 5 & kill -s INT %sleep; wait; kill -0 $$; echo $?"),
+        "0\n"
+    );
+    assert_eq!(
+        stdout("kill -l 9 143 TERM sigint; kill -l | head -1"),
+        "KILL\nTERM\n15\n2\n 1) SIGHUP  \t 2) SIGINT  \t 3) SIGQUIT \t 4) SIGILL  \t 5) SIGTRAP\n"
+    );
+    let out = sh("kill %3; echo $?; kill -FOO 1; echo $?; kill -- -x");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "1\n1\n");
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "kill: %3: no such job\nError: kill: FOO: invalid signal specification\n\
+         kill: -x: arguments must be process or job IDs\n"
+    );
+}
+
+#[test]
+fn trap_builtin() {
+    assert_eq!(
+        stdout("trap 'echo bye $?' EXIT; trap 'echo err $?' ERR; false; echo $?; trap -p; exit 4"),
+        "err 1\n1\ntrap -- 'echo bye $?' EXIT\ntrap -- 'echo err $?' ERR\nbye 4\n"
+    );
+    assert_eq!(sh("trap 'exit 7' EXIT; exit 1").status.code(), Some(7));
+    // Not in conditions, and errexit exits after it
+    assert_eq!(
+        stdout("set -e; trap 'echo err' ERR; trap 'echo exit' EXIT; if false; then :; fi; false; echo no"),
+        "err\nexit\n"
+    );
+    // Signals are handled between commands, `-` resets and an empty action ignores
+    assert_eq!(
+        stdout("trap 'echo int' INT; kill -INT $$; echo next; trap - INT; trap -p INT; trap '' TERM; kill $$; echo alive"),
+        "int\nnext\nalive\n"
+    );
+    assert_eq!(
+        stdout("f() { return 2; }; trap 'echo ret $?' RETURN; f; trap 'echo debug' DEBUG; echo a"),
+        "ret 2\ndebug\na\n"
+    );
+    // A subshell starts with no traps but its own
+    assert_eq!(
+        stdout("trap 'echo parent' EXIT; (trap -p; trap 'echo child' EXIT); echo end"),
+        "child\nend\nparent\n"
+    );
+    let out = sh("trap 'echo t' FOO; echo $?");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "1\n");
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "trap: FOO: invalid signal specification\n"
+    );
+}
+
+#[test]
+fn ulimit_and_umask() {
+    // Children inherit them
+    assert_eq!(
+        stdout("ulimit -n 256; ulimit -n; sh -c 'ulimit -n'; ulimit -S -c 0; ulimit -c; ulimit -t 5; ulimit -Ht"),
+        "256\n256\n0\n5\n"
+    );
+    assert_eq!(
+        stdout("ulimit -v unlimited; ulimit -a | grep -e -v"),
+        "virtual memory              (kbytes, -v) unlimited\n"
+    );
+    assert_eq!(
+        stdout("umask 027; umask; umask -S; umask g+w,o-r; umask -p; umask u=rwx,go=; sh -c umask"),
+        "0027\nu=rwx,g=rx,o=\numask 0007\n0077\n"
+    );
+    let out = sh("ulimit -n abc; ulimit -x; umask 8; umask u; echo $?");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "1\n");
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "Error: ulimit: abc: invalid number\nError: ulimit: -x: invalid option\n\
+         Error: umask: 8: invalid mode\nError: umask: u: invalid mode\n"
+    );
+}
+
+#[test]
+fn time_keyword() {
+    // The whole pipeline is timed, on stderr, and keeps its status
+    let out = sh("time sleep 0.2 | sleep 0.2; echo $?; time -p ! true; echo $?");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "0\n1\n");
+    let err = String::from_utf8(out.stderr).unwrap();
+    let lines: Vec<&str> = err.lines().collect();
+    assert_eq!(lines.len(), 7, "{}", err);
+    assert!(
+        lines[0].is_empty() && lines[1].starts_with("real\t0m0.2"),
+        "{}",
+        err
+    );
+    assert!(lines[2].starts_with("user\t0m") && lines[3].starts_with("sys\t0m"));
+    assert_eq!(lines[4..], ["real 0.00", "user 0.00", "sys 0.00"]);
+
+    let time = |format: &str| {
+        let out = Command::new(env!("CARGO_BIN_EXE_unix_shell"))
+            .args(["-c", "time sleep 0.1 2>/dev/null"])
+            .env("TIMEFORMAT", format)
+            .output()
+            .unwrap();
+        String::from_utf8(out.stderr).unwrap()
+    };
+    assert_eq!(time("took %0R %% %Z"), "took 0 % %Z\n");
+    assert_eq!(time(""), "");
+    // A background job reaped while timing is not charged to the pipeline
+    let out = sh("(while :; do :; done) & sleep 0.4; kill %1; TIMEFORMAT=%U; time { wait %1; }");
+    let user: f64 = String::from_utf8(out.stderr)
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    assert!(user < 0.1, "{}", user);
+
+    let times = stdout("times");
+    assert_eq!(times.lines().count(), 2);
+    for line in times.lines() {
+        assert!(
+            line.split(' ')
+                .all(|t| t.starts_with("0m") && t.ends_with('s')),
+            "{}",
+            times
+        );
+    }
+}
+
+#[test]
+fn exec_builtin() {
+    let file = std::env::temp_dir().join(format!("exec-{}", std::process::id()));
+    let file = file.to_str().unwrap();
+    // The redirections of a lone `exec` stay, and children inherit them
+    let out = sh(&format!(
+        "exec 3>{0}; echo a >&3; sh -c 'echo b >&3'; exec 3>&-; cat {0}; exec 2>&1; \
+         echo c >&3; echo $?; exec 4<{0}; read x <&4; echo $x",
+        file
+    ));
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "a\nb\nError: 3: Bad file number\n1\na\n"
+    );
+    std::fs::remove_file(file).unwrap();
+    // The shell is replaced, and nothing after runs
+    assert_eq!(stdout("exec echo replaced; echo not"), "replaced\n");
+    assert_eq!(stdout("exec -c env"), "");
+    let out = sh("exec nonexistent-cmd; echo not");
+    assert_eq!(out.status.code(), Some(127));
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "Error: exec: nonexistent-cmd: No such file or directory\n"
+    );
+}
+
+#[test]
+fn command_lookup() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = std::env::temp_dir().join(format!("lookup-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("mine");
+    std::fs::write(&script, "#!/bin/sh\necho mine \"$@\"\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let run = |script: &str| {
+        let out = Command::new(env!("CARGO_BIN_EXE_unix_shell"))
+            .args(["-c", script])
+            .env("PATH", format!("{}:/usr/bin:/bin", dir.display()))
+            .output()
+            .unwrap();
+        let text = String::from_utf8(out.stdout).unwrap() + &String::from_utf8(out.stderr).unwrap();
+        text.replace(dir.to_str().unwrap(), "D")
+    };
+    assert_eq!(
+        run("f() { :; }; type f cd if mine; type -t f cd if mine nosuch; echo $?"),
+        "f is a function\nf() { :; }\ncd is a shell builtin\n\
+         if is a shell keyword\nmine is D/mine\nfunction\nbuiltin\nkeyword\nfile\n1\n"
+    );
+    // Programs are remembered with their hits, until PATH changes or `hash -r`
+    assert_eq!(
+        run("mine a; mine b; type mine; hash; hash -t mine; hash -r; hash; hash -p /bin/echo mine; mine c"),
+        "mine a\nmine b\nmine is hashed (D/mine)\nhits\tcommand\n   2\tD/mine\nD/mine\n\
+         hash: hash table empty\nc\n"
+    );
+    assert_eq!(
+        run("mine() { echo fn; }; mine; command mine x; command -v mine cd; builtin echo b"),
+        "fn\nmine x\nmine\ncd\nb\n"
+    );
+    assert_eq!(
+        run("nosuch; echo $?; nosuch 2>/dev/null; builtin nosuch; type nosuch; hash nosuch"),
+        "127\nError: nosuch: command not found\nError: builtin: nosuch: not a shell builtin\n\
+         type: nosuch: not found\nhash: nosuch: not found\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn assignments() {
+    // Before a command, only its environment has them
+    assert_eq!(
+        stdout("FOO=1 BAR=$FOO sh -c 'echo $FOO $BAR'; echo \"[$FOO]\""),
+        "1 1\n[]\n"
+    );
+    assert_eq!(
+        stdout("A=1 B=$A; echo $A $B; sh -c 'echo [$A]'"),
+        "1 1\n[]\n"
+    );
+    assert_eq!(
+        stdout("f() { echo $V; }; V=3 f; echo \"[$V]\"; V=4; V=5 true; echo $V"),
+        "3\n[]\n4\n"
+    );
+    // Quoted, or after the command name, it is a word like any other
+    assert_eq!(stdout("echo X=1; 'X=1' 2>/dev/null; echo $?"), "X=1\n127\n");
+    let out = sh("set -x; Q='a b' true; E=");
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "+ Q='a b' true\n+ E=''\n"
+    );
+}
+
+#[test]
+fn parameter_expansion() {
+    // Defaults, with `:` an empty value counts as unset
+    assert_eq!(
+        stdout("e=; a=1; echo ${u:-d} [${e-d}] ${e:-d} ${a:+alt} [${u:+alt}]"),
+        "d [] d alt []\n"
+    );
+    assert_eq!(stdout("echo ${u:=x y} $u; echo ${1:=z}"), "x y x y\n");
+    assert_eq!(
+        String::from_utf8(sh("echo ${u:?}; echo no").stderr).unwrap(),
+        "Error: u: parameter null or not set\n"
+    );
+    assert_eq!(
+        String::from_utf8(sh("echo ${u?missing $HOME}").stderr).unwrap(),
+        format!("Error: u: missing {}\n", std::env::var("HOME").unwrap())
+    );
+    // The word is split unless quoted, and may hold other expansions
+    assert_eq!(
+        stdout(
+            "set -- p q; for w in ${u:-a \"b c\"} \"${u:-d e}\" ${u:-$@}; do echo \"<$w>\"; done"
+        ),
+        "<a>\n<b c>\n<d e>\n<p>\n<q>\n"
+    );
+    assert_eq!(stdout("echo ${u:-${v:-nested}} ${u:-'}'}"), "nested }\n");
+    // Inside double quotes a `'` in the word is a plain character, patterns still quote
+    assert_eq!(
+        stdout("v=abc; echo \"${u:-'x'}\" ${u:-'x'} \"${u:-a\\b}\" \"${v#'a'}\""),
+        "'x' x a\\b bc\n"
+    );
+    // Length, prefix and suffix removal
+    assert_eq!(
+        stdout("f=dir/a.tar.gz; set -- 1 2 3; echo ${#f} ${#@} ${f#*/} ${f##*.} ${f%.*} ${f%%.*}"),
+        "12 3 a.tar.gz gz dir/a.tar dir/a\n"
+    );
+    assert_eq!(stdout("f='a*b'; echo ${f#\"a*\"} ${f%'*'b}"), "b a\n");
+    // Substrings, from the end for a negative offset or length
+    assert_eq!(
+        stdout("s=hello; echo ${s:1} ${s:1:3} ${s: -3} ${s:1:-1} [${s:9}]"),
+        "ello ell llo ell []\n"
+    );
+    assert_eq!(
+        stdout("set -- a b c d; echo ${@:2} ${@:2:2}"),
+        "b c d b c\n"
+    );
+    // Replacement
+    assert_eq!(
+        stdout("s=a-b-c; echo ${s/-/+} ${s//-/+} ${s/#a/X} ${s/%c/Z} ${s//-} ${s/b*/Q}"),
+        "a+b-c a+b+c X-b-c a-b-Z abc a-Q\n"
+    );
+    assert_eq!(
+        stdout("s=abc; echo ${s/#/X} ${s/%/Y} ${s/}"),
+        "Xabc abcY abc\n"
+    );
+    // Case
+    assert_eq!(
+        stdout("s='hello World'; echo ${s^} ${s^^} ${s,,} ${s^^[lo]}"),
+        "Hello World HELLO WORLD hello world heLLO WOrLd\n"
+    );
+    let out = sh("echo ${s:x}; echo no");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "Error: ${s:x}: bad substitution\n"
+    );
+    // A negative length cannot end before the offset
+    let out = sh("x=abc; echo ${x:1:-5}; echo no");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "Error: -5: substring expression < 0\n"
+    );
+}
+
+#[test]
+fn pipestatus() {
+    assert_eq!(
+        stdout("false | true | (exit 3); echo $? ${PIPESTATUS[@]}"),
+        "3 1 0 3\n"
+    );
+    assert_eq!(
+        stdout("! false | true; echo $? ${PIPESTATUS[*]}"),
+        "1 1 0\n"
+    );
+    assert_eq!(stdout("(exit 2); echo ${PIPESTATUS[0]}"), "2\n");
+    assert_eq!(
+        stdout("set -o pipefail; (exit 2) | false | true; echo $?; true | true; echo $?"),
+        "1\n0\n"
+    );
+    assert_eq!(
+        stdout("set -o pipefail; set +o pipefail; false | true; echo $?"),
+        "0\n"
+    );
+    assert_eq!(sh("set -o nope").status.code(), Some(1));
+}
+
+#[test]
+fn set_options() {
+    assert_eq!(stdout("set -e; echo a; false; echo b"), "a\n");
+    assert_eq!(sh("set -e; true && false; echo b").status.code(), Some(1));
+    // Conditions, `!` and all but the last of `&&` and `||` do not exit
+    assert_eq!(
+        stdout("set -e; if false; then :; fi; while false; do :; done; false || true; false && true; ! true; echo ok"),
+        "ok\n"
+    );
+    assert_eq!(
+        stdout("set -e; f() { false; echo in f; }; f && echo yes; f; echo no"),
+        "in f\nyes\n"
+    );
+    assert_eq!(stdout("set -eo pipefail; false | true; echo no"), "");
+
+    assert_eq!(
+        stdout("set -u; echo \"$@\" ok; echo $unset; echo no"),
+        "ok\n"
+    );
+    assert_eq!(sh("set -u; echo $unset").status.code(), Some(1));
+    assert_eq!(stdout("set -u; set +u; echo $unset ok"), "ok\n");
+
+    let out = Command::new(env!("CARGO_BIN_EXE_unix_shell"))
+        .args(["-c", "set -x; echo 'a b' c >/dev/null; set +x; echo quiet"])
+        .env("PS4", "> ")
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "> echo 'a b' c\n> set +x\n"
+    );
+
+    assert_eq!(stdout("set -eu; echo $-; set +eu; echo $-"), "eu\n\n");
+    assert_eq!(
+        stdout("set -o nounset; set -o | grep nounset; set +o | grep nounset"),
+        "nounset        \ton\nset -o nounset\n"
+    );
+    assert_eq!(
+        stdout("set a 'b c'; echo $# $2; set --; echo $#"),
+        "2 b c\n0\n"
+    );
+    assert_eq!(sh("set -q").status.code(), Some(1));
+}
+
+#[test]
+fn builtins() {
+    assert_eq!(
+        stdout("printf '%d|%5d|%-5d|%05d|%+d|%.3d|%x|%#X|%#o|%u\\n' 42 42 42 42 42 7 255 255 8 -1"),
+        "42|   42|42   |00042|+42|007|ff|0XFF|010|18446744073709551615\n"
+    );
+    assert_eq!(
+        stdout("printf '%f %.2f %e %g %g %G\\n' 3.14159 2.5 12345.678 0.0001 1000000 1e-5"),
+        "3.141590 2.50 1.234568e+04 0.0001 1e+06 1E-05\n"
+    );
+    assert_eq!(
+        stdout("printf '%5.2s|%-4s|%c|%%|%*d\\n' abc x yz 3 1"),
+        "   ab|x   |y|%|  1\n"
+    );
+    // The format is reused for the remaining arguments
+    assert_eq!(stdout("printf '%s=%s\\n' a 1 b"), "a=1\nb=\n");
+    assert_eq!(
+        stdout("printf '%d %b|\\101\\n' \"'A\" 'x\\ty\\0102'"),
+        "65 x\tyB|A\n"
+    );
+    assert_eq!(
+        stdout("printf '%d\\n' 0x10 010 nan; echo $?"),
+        "16\n8\n0\n1\n"
+    );
+    assert_eq!(sh("printf '%z'").status.code(), Some(1));
+    // A width or precision past INT_MAX is an error, not an allocation
+    let out = sh("printf '%99999999999999999999d' 1; printf '%*d' -3000000000 1; echo $?");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "1\n");
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "Error: printf: 99999999999999999999: invalid field width\n\
+         Error: printf: -3000000000: invalid field width\n"
+    );
+
+    assert_eq!(
+        stdout("echo -n a; echo -e 'b\\tc\\c d'; echo -E 'x\\ty' -n"),
+        "ab\tcx\\ty -n\n"
+    );
+    assert_eq!(stdout("true && : && ! false && echo ok"), "ok\n");
+    assert_eq!(stdout("cd /; pwd; cd /tmp && pwd -P"), "/\n/tmp\n");
+    // Output goes through redirections and pipes, even without a newline
+    assert_eq!(
+        stdout("printf x > /dev/null; echo b | tr b c; printf '%s\\n' 2 1 | sort"),
+        "c\n1\n2\n"
+    );
+    assert_eq!(
+        stdout("while true; do echo y; done | head -2; echo ${PIPESTATUS[@]}"),
+        "y\ny\n141 0\n"
+    );
+}
+
+#[test]
+fn read_builtin() {
+    assert_eq!(
+        stdout("printf '  a  b   c d  \\n' | { read x y; echo \"[$x][$y]\"; }"),
+        "[a][b   c d]\n"
+    );
+    assert_eq!(
+        stdout("printf '  a \\n' | { read; echo \"[$REPLY]\"; }"),
+        "[  a ]\n"
+    );
+    assert_eq!(
+        stdout("printf 'a\\\\ b\\\\\\nc d\\n' | { read x y; echo \"[$x][$y]\"; }"),
+        "[a bc][d]\n"
+    );
+    assert_eq!(
+        stdout("printf 'a\\\\ b c\\n' | { read -r x y; echo \"[$x][$y]\"; }"),
+        "[a\\][b c]\n"
+    );
+    assert_eq!(
+        stdout("printf '1 2 3\\n4\\n' | { read -a arr; echo ${arr[2]}; read z; echo $z; }"),
+        "3\n4\n"
+    );
+    // Nothing past what was asked for is consumed
+    assert_eq!(
+        stdout("printf abcdef | { read -n 3 x; echo $x $?; read y; echo $y $?; }"),
+        "abc 0\ndef 1\n"
+    );
+    assert_eq!(
+        stdout("printf 'a,b;c' | { read -d ';' x; echo $x; read y; echo $y; }"),
+        "a,b\nc\n"
+    );
+    assert_eq!(
+        stdout("{ read a; read b; } <<EOF\none\ntwo\nEOF\necho $a $b"),
+        "one two\n"
+    );
+    assert_eq!(stdout("sleep 1 | { read -t 0.2 x; echo $?; }"), "142\n");
+    assert_eq!(stdout("read x < /dev/null; echo $?"), "1\n");
+    assert_eq!(sh("read 1x").status.code(), Some(1));
+
+    let out = Command::new(env!("CARGO_BIN_EXE_unix_shell"))
+        .args(["-c", "printf 'a:b::c\\n' | { read -a p; echo ${p[@]}; }"])
+        .env("IFS", ":")
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "a b  c\n");
+}
+
+#[test]
+fn source_and_eval() {
+    let dir = std::env::temp_dir().join(format!("source-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("lib.sh"),
+        "echo \"in $# $1\"\nf() { echo f $1; return 4; echo no; }\nreturn 3\necho never\n",
+    )
+    .unwrap();
+    let script = format!(
+        "cd {}; set -- x y; . ./lib.sh a; echo $? $# $1; f z; echo $?; source lib.sh; . missing",
+        dir.display()
+    );
+    let out = sh(&script);
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "in 1 a\n3 2 x\nf z\n4\nin 2 x\n"
+    );
+    assert_eq!(out.status.code(), Some(1));
+    std::fs::remove_dir_all(dir).unwrap();
+
+    assert_eq!(
+        stdout("eval 'echo a; echo b | tr b c'; eval false; echo $?; eval; echo $?"),
+        "a\nc\n1\n0\n"
+    );
+    assert_eq!(stdout("eval 'for i in 1 2; do echo $i; done'"), "1\n2\n");
+    assert_eq!(
+        stdout("f() { for i in 1 2 3; do if test $i = 2; then return 7; fi; echo $i; done; }; f; echo $?"),
+        "1\n7\n"
+    );
+    assert_eq!(stdout("f() { false; return; }; f; echo $?"), "1\n");
+    assert_eq!(stdout("return 2; echo $?"), "1\n");
+}
+
+#[test]
+fn cd_builtin() {
+    let dir = std::env::temp_dir().join(format!("cd-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("real/sub")).unwrap();
+    std::fs::create_dir_all(dir.join("cdpath/proj")).unwrap();
+    std::os::unix::fs::symlink(dir.join("real"), dir.join("link")).unwrap();
+    let run = |script: &str| {
+        let out = Command::new(env!("CARGO_BIN_EXE_unix_shell"))
+            .args(["-c", script])
+            .current_dir(&dir)
+            .env("HOME", dir.join("real"))
+            .env("CDPATH", dir.join("cdpath"))
+            .output()
+            .unwrap();
+        let text = String::from_utf8(out.stdout).unwrap();
+        text.replace(dir.to_str().unwrap(), "D")
+    };
+    assert_eq!(run("cd; pwd; echo $OLDPWD"), "D/real\nD\n");
+    // Logical paths go back through symlinks, physical ones resolve them
+    assert_eq!(
+        run("cd link/sub; pwd; pwd -P; cd ..; pwd; cd -P ..; pwd"),
+        "D/link/sub\nD/real/sub\nD/link\nD\n"
+    );
+    assert_eq!(run("cd -P link; echo $PWD"), "D/real\n");
+    assert_eq!(run("cd real; cd -; cd -; echo $OLDPWD"), "D\nD/real\nD\n");
+    assert_eq!(
+        run("cd proj; pwd; cd ./proj; echo $?"),
+        "D/cdpath/proj\nD/cdpath/proj\n1\n"
+    );
+    assert_eq!(run("cd real//./sub/..; sh -c 'echo $PWD'"), "D/real\n");
+    // From a removed directory, `..` is taken from PWD, or physically without it
+    assert_eq!(
+        run("mkdir gone; cd gone; rmdir ../gone; cd ..; pwd; echo $OLDPWD"),
+        "D\nD/gone\n"
+    );
+    assert_eq!(
+        run("mkdir gone; cd gone; rmdir ../gone; PWD=; cd ..; pwd"),
+        "D\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let out = sh("cd /nonexistent; cd a b 2>/dev/null; echo $?");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "1\n");
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "Error: cd: /nonexistent: No such file or directory\n"
+    );
+}
+
+#[test]
+fn dir_stack() {
+    let dir = std::env::temp_dir().join(format!("dirs-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("a")).unwrap();
+    std::fs::create_dir_all(dir.join("b")).unwrap();
+    let run = |script: &str| {
+        let out = Command::new(env!("CARGO_BIN_EXE_unix_shell"))
+            .args(["-c", script])
+            .current_dir(&dir)
+            .env("HOME", &dir)
+            .output()
+            .unwrap();
+        String::from_utf8(out.stdout).unwrap() + &String::from_utf8(out.stderr).unwrap()
+    };
+    assert_eq!(run("pushd a; pushd ../b"), "~/a ~\n~/b ~/a ~\n");
+    assert_eq!(
+        run("pushd a >/dev/null; pushd ../b >/dev/null; dirs -v; pushd +2; pwd"),
+        " 0  ~/b\n 1  ~/a\n 2  ~\n~ ~/b ~/a\n".to_string() + dir.to_str().unwrap() + "\n"
+    );
+    assert_eq!(
+        run("pushd a >/dev/null; pushd >/dev/null; echo $OLDPWD; popd; pwd; dirs -l"),
+        format!("{0}/a\n~/a\n{0}/a\n{0}/a\n", dir.display())
+    );
+    assert_eq!(
+        run("pushd -n b; popd +1; dirs -0; dirs -c; dirs"),
+        "~ b\n~\n~\n~\n"
+    );
+    assert_eq!(
+        run("popd; pushd; pushd +3; pushd c; echo $?"),
+        "1\nError: popd: directory stack empty\nError: pushd: no other directory\n\
+         Error: pushd: +3: directory stack index out of range\n\
+         Error: pushd: c: No such file or directory\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
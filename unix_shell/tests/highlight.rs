@@ -0,0 +1,48 @@
+use unix_shell::highlight::{highlight, Theme};
+
+// Single letters instead of real colours, so the expected output stays readable
+fn theme() -> Theme {
+    Theme::parse(
+        "command=c:unknown=u:keyword=k:string=s:operator=o:redirect=r:variable=v:comment=#",
+    )
+}
+
+fn hl(line: &str) -> String {
+    highlight(line, &theme(), |name| name == "ls" || name == "cat")
+        .replace("\x1b[0m", "]")
+        .replace("\x1b[", "[")
+}
+
+#[test]
+fn commands() {
+    assert_eq!(hl("ls -l"), "[cmls] -l");
+    assert_eq!(hl("nope"), "[umnope]");
+    assert_eq!(
+        hl("ls | nope && cat"),
+        "[cmls] [om|] [umnope] [om&&] [cmcat]"
+    );
+    assert_eq!(hl("> f ls"), "[om>] [rmf] [cmls]");
+    assert_eq!(
+        hl("if ls; then cat; fi"),
+        "[kmif] [cmls][om;] [kmthen] [cmcat][om;] [kmfi]"
+    );
+    assert_eq!(
+        hl("for ls in a; do ls; done"),
+        "[kmfor] ls in a[om;] [kmdo] [cmls][om;] [kmdone]"
+    );
+}
+
+#[test]
+fn words() {
+    assert_eq!(hl("cat 2>&1"), "[cmcat] [om2>&][rm1]");
+    assert_eq!(
+        hl("ls \"$HOME/a\" '$x'"),
+        "[cmls] [sm\"][vm$HOME][sm/a\"] [sm'$x']"
+    );
+    assert_eq!(hl("ls ${a}b \\$c"), "[cmls] [vm${a}]b \\$c");
+    assert_eq!(hl("ls # $x"), "[cmls] [#m# $x]");
+    assert_eq!(
+        hl("cat <<EOF\n$x\nEOF"),
+        "[cmcat] [om<<][rmEOF]\n[vm$x][sm\n]EOF"
+    );
+}
@@ -0,0 +1,204 @@
+use unix_shell::lex::{lex, lex_spans, LexError, Quote, Token, Word, WordPart};
+
+// A small xorshift generator, so the fuzz cases are reproducible without extra dependencies
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn gen_line(&mut self, alphabet: &[char]) -> String {
+        let len = self.next() % 24;
+        (0..len)
+            .map(|_| alphabet[(self.next() % alphabet.len() as u64) as usize])
+            .collect()
+    }
+}
+
+const ALPHABET: &[char] = &[
+    'a', 'b', 'x', '0', ' ', '\t', '\n', '\\', '"', '\'', '$', '|', '<', '>', '&', '\0', 'é', '中',
+];
+
+// Same as ALPHABET, but without the '\0' that stops the lexer early
+const NO_NUL: &[char] = &[
+    'a', 'b', ' ', '\n', '\\', '"', '\'', '|', '<', '>', '&', 'é',
+];
+
+fn words(line: &str) -> Vec<Word> {
+    lex(line)
+        .unwrap()
+        .into_iter()
+        .map(|t| match t {
+            Token::Word(w) => w,
+            t => panic!("unexpected token {:?}", t),
+        })
+        .collect()
+}
+
+fn literals(line: &str) -> Vec<String> {
+    words(line).iter().map(Word::literal).collect()
+}
+
+#[test]
+fn braced_parameters() {
+    // Blanks, quotes and braces inside `${...}` do not end the word
+    assert_eq!(
+        literals("echo ${a:-x y} ${b:-'}'}x \"${c#\"{\"}\" ${d:-${e}}"),
+        vec![
+            "echo",
+            "${a:-x y}",
+            "${b:-'}'}x",
+            "${c#\"{\"}",
+            "${d:-${e}}"
+        ]
+    );
+}
+
+#[test]
+fn malformed() {
+    assert_eq!(lex("ls \\"), Err(LexError::TrailingBackslash));
+    assert_eq!(
+        lex("echo \"abc"),
+        Err(LexError::UnterminatedQuote(Quote::Double))
+    );
+    assert_eq!(
+        lex("echo 'abc"),
+        Err(LexError::UnterminatedQuote(Quote::Single))
+    );
+    assert_eq!(
+        lex("echo $'abc\\'"),
+        Err(LexError::UnterminatedQuote(Quote::AnsiC))
+    );
+    assert_eq!(lex("echo ${a:-b"), Err(LexError::UnterminatedBrace));
+    assert_eq!(
+        lex("ec\\\nho hi").unwrap(),
+        vec![Token::Word("echo".into()), Token::Word("hi".into())]
+    );
+}
+
+#[test]
+fn quoting() {
+    assert_eq!(literals(r#"a"b c"'d e'f"#), ["ab cd ef"]);
+    assert_eq!(literals(r#"'a\b"$x'"#), [r#"a\b"$x"#]);
+    assert_eq!(literals(r#""\$x\"\\\q""#), [r#"$x"\\q"#]);
+    assert_eq!(literals(r"\a\ b"), ["a b"]);
+    assert_eq!(literals(r"$'\n\t\x41\101é\cA\'\z'"), ["\n\tAAé\x01'\\z"]);
+    // Octal takes at most three digits, and an escape that is not a code point stays as is
+    assert_eq!(literals(r"$'\1011\0'"), ["A1\0"]);
+    assert_eq!(
+        literals(r"$'\U0000D800\U110000x\xg'"),
+        [r"\U0000D800\U110000x\xg"]
+    );
+    assert_eq!(literals(r#""" ''"#), ["", ""]);
+    // Provenance is kept for the expansion stage
+    let part = |text: &str, quote| WordPart {
+        text: text.to_string(),
+        quote,
+    };
+    assert_eq!(
+        words(r#"$a"$b"'$c'\$d"#)[0].0,
+        vec![
+            part("$a", Quote::Unquoted),
+            part("$b", Quote::Double),
+            part("$c", Quote::Single),
+            part("$", Quote::Escaped),
+            part("d", Quote::Unquoted),
+        ]
+    );
+}
+
+#[test]
+fn fuzz_never_panics() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..20000 {
+        let line = rng.gen_line(ALPHABET);
+        let _ = lex(&line);
+    }
+}
+
+#[test]
+fn fuzz_properties() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..20000 {
+        let s = rng.gen_line(NO_NUL);
+        let t = rng.gen_line(NO_NUL);
+        // Here-document bodies have their own quoting rules
+        if s.contains("<<") {
+            continue;
+        }
+        let Ok(toks) = lex(&s) else {
+            continue;
+        };
+        // A dangling backslash always asks for more input
+        assert_eq!(lex(&format!("{s}\\")), Err(LexError::TrailingBackslash));
+        // An opening quote that is never closed is reported
+        assert_eq!(
+            lex(&format!("{s}\"")),
+            Err(LexError::UnterminatedQuote(Quote::Double))
+        );
+        // Backslash-newline disappears entirely
+        assert_eq!(lex(&format!("{s}\\\n{t}")), lex(&format!("{s}{t}")));
+        assert_eq!(lex(&format!("{s}\\\n")), Ok(toks));
+    }
+}
+
+fn heredoc_body(line: &str) -> Word {
+    lex(line)
+        .unwrap()
+        .into_iter()
+        .find_map(|t| match t {
+            Token::HereDoc(doc) => Some(doc.body),
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn heredoc() {
+    assert_eq!(heredoc_body("cat <<EOF\na $x\nEOF\n").literal(), "a $x\n");
+    assert_eq!(heredoc_body("cat <<-EOF\n\ta\n\tEOF").literal(), "a\n");
+    assert_eq!(heredoc_body("cat <<EOF\na\\\nb\nEO\\\nF").literal(), "ab\n");
+    assert_eq!(heredoc_body("cat <<'EOF'\na\\\nEOF\n").literal(), "a\\\n");
+    // A quoted delimiter turns off expansions in the body
+    assert_eq!(heredoc_body("cat <<EOF\n$x\nEOF").0[0].quote, Quote::Double);
+    assert_eq!(
+        heredoc_body("cat <<\"EOF\"\n$x\nEOF").0[0].quote,
+        Quote::Single
+    );
+    assert_eq!(
+        lex("cat <<EOF\nabc"),
+        Err(LexError::UnterminatedHereDoc("EOF".into()))
+    );
+    assert_eq!(lex("cat <<EOF\nabc\\"), Err(LexError::TrailingBackslash));
+    // Bodies are read after the line that contains the operator
+    let toks = lex("cat <<A; cat <<B\na\nA\nb\nB\necho").unwrap();
+    assert_eq!(
+        toks.iter().filter(|t| matches!(t, Token::Newline)).count(),
+        1
+    );
+    assert!(matches!(toks.last(), Some(Token::Word(w)) if w.literal() == "echo"));
+}
+
+#[test]
+fn spans() {
+    let line = "ls -l 2>>'out file' | wc # count";
+    let spans = lex_spans(line);
+    let text: Vec<&str> = spans.tokens.iter().map(|(_, r)| &line[r.clone()]).collect();
+    assert_eq!(text, ["ls", "-l", "2", ">>", "'out file'", "|", "wc"]);
+    assert_eq!(spans.quotes, [(Quote::Single, 9..19)]);
+    assert_eq!(spans.comments, vec![25..32]);
+    assert_eq!(spans.error, None);
+
+    // Tokens up to the error are kept
+    let spans = lex_spans("echo a \"b");
+    assert_eq!(spans.tokens.len(), 2);
+    assert_eq!(spans.quotes, [(Quote::Double, 7..9)]);
+    assert_eq!(
+        spans.error,
+        Some(LexError::UnterminatedQuote(Quote::Double))
+    );
+}
@@ -1,10 +1,10 @@
-use unix_shell::ast::parse;
+use unix_shell::ast::{is_incomplete, parse, AndOrOp, Command, Compound, RedirectKind, TimeFormat};
 use unix_shell::eval::check_prog;
 use unix_shell::lex::lex;
 
 fn run_test(s: &str, pass: bool) {
     let line = String::from(s);
-    let l = lex(&line);
+    let l = lex(&line).unwrap();
     let ast = parse(l).unwrap();
     if pass {
         check_prog(&ast).unwrap();
@@ -24,3 +24,154 @@ fn syntax() {
     run_test("ls | cat > b | m", false);
     run_test("ls | cat < a", false);
 }
+
+#[test]
+fn redirect_anywhere() {
+    let parse_str = |s: &str| parse(lex(s).unwrap());
+    for s in ["ls > out -l", "> out ls -l", "ls -l>out"] {
+        let list = parse_str(s).unwrap();
+        let Command::Simple(cmd) = &list[0].0.first.cmds[0] else {
+            panic!("{} should be a simple command", s);
+        };
+        let args: Vec<String> = cmd.args.iter().map(|w| w.literal()).collect();
+        assert!(args == ["ls", "-l"], "{}: {:?}", s, args);
+        assert!(
+            matches!(&cmd.redirects[..], [r] if r.kind == RedirectKind::Out && r.target.literal() == "out")
+        );
+    }
+    run_test("< in cat -n > out", true);
+    run_test("cat < in | < a cat", false);
+    run_test("> a cat > b", false);
+    assert!(parse_str("ls >").is_err());
+    assert!(parse_str("ls > | cat").is_err());
+    assert!(parse_str("ls |").is_err());
+}
+
+#[test]
+fn grammar() {
+    let parse_str = |s: &str| parse(lex(s).unwrap());
+    let list = parse_str("a | b && c || ! d; e &").unwrap();
+    assert_eq!(list.len(), 2);
+    let (and_or, bg) = &list[0];
+    assert!(!bg && list[1].1);
+    assert_eq!(and_or.first.cmds.len(), 2);
+    let ops: Vec<AndOrOp> = and_or.rest.iter().map(|(op, _)| *op).collect();
+    assert_eq!(ops, [AndOrOp::And, AndOrOp::Or]);
+    assert!(and_or.rest[1].1.bang);
+
+    let compound = |s: &str| match &parse_str(s).unwrap()[0].0.first.cmds[0] {
+        Command::Compound(c, _) => c.clone(),
+        c => panic!("{}: {:?}", s, c),
+    };
+    assert!(
+        matches!(compound("if a; then b; elif c; then d; else e; fi"), Compound::If(b, Some(_)) if b.len() == 2)
+    );
+    assert!(matches!(
+        compound("while a\ndo b; done"),
+        Compound::While(..)
+    ));
+    assert!(
+        matches!(compound("for i in 1 2; do echo $i; done"), Compound::For(n, Some(w), _) if n == "i" && w.len() == 2)
+    );
+    assert!(matches!(
+        compound("for i do :; done"),
+        Compound::For(_, None, _)
+    ));
+    assert!(
+        matches!(compound("case x in a|b) ;; *) c;; esac"), Compound::Case(_, items) if items.len() == 2)
+    );
+    assert!(matches!(compound("{ a; b; } > out"), Compound::Brace(l) if l.len() == 2));
+    assert!(matches!(compound("(a & b)"), Compound::Subshell(_)));
+    assert!(
+        matches!(&parse_str("f() { a; }").unwrap()[0].0.first.cmds[0], Command::Function(n, _) if n == "f")
+    );
+    let timed = &parse_str("time -p ! a | b").unwrap()[0].0.first;
+    assert!(timed.time == Some(TimeFormat::Posix) && timed.bang && timed.cmds.len() == 2);
+    assert_eq!(timed.to_string(), "time -p ! a | b");
+    // Assignments only before the command name
+    match &parse_str("A=1 B='x y' >out cmd C=2").unwrap()[0]
+        .0
+        .first
+        .cmds[0]
+    {
+        Command::Simple(c) => {
+            let names: Vec<&str> = c.assigns.iter().map(|(n, _)| n.as_str()).collect();
+            assert_eq!(names, ["A", "B"]);
+            assert_eq!(c.args.len(), 2);
+            assert_eq!(c.to_string(), "A=1 B='x y' cmd C=2 >out");
+        }
+        c => panic!("{:?}", c),
+    }
+    assert!(parse_str("A=1").is_ok() && parse_str("'A'=1 x").is_ok());
+
+    for s in [
+        "if a; then b; fi fi",
+        "then",
+        "{ a }",
+        "while a; do done",
+        "f() a",
+        "a && ",
+        "( )",
+        "a;;",
+    ] {
+        assert!(parse_str(s).is_err(), "{} should not parse", s);
+    }
+    assert_eq!(
+        parse_str("if a").unwrap_err(),
+        unix_shell::ast::ParseError::UnexpectedEof
+    );
+}
+
+#[test]
+fn incomplete() {
+    for s in [
+        "echo 'a",
+        "echo \"a",
+        "ls \\",
+        "ls |",
+        "true &&",
+        "false ||",
+        "if true",
+        "if true; then",
+        "while true; do",
+        "for i in a b",
+        "case a in",
+        "{ ls",
+        "( ls",
+        "f() {",
+        "cat <<EOF",
+        "cat <<EOF\nabc",
+    ] {
+        assert!(is_incomplete(s), "{:?} should be incomplete", s);
+    }
+    for s in [
+        "ls",
+        "ls;",
+        "if true; then ls; fi",
+        "cat <<EOF\nabc\nEOF",
+        "ls )",
+        "fi",
+        "ls &",
+    ] {
+        assert!(!is_incomplete(s), "{:?} should be complete", s);
+    }
+}
+
+#[test]
+fn display() {
+    for s in [
+        "a | b && ! c || d",
+        "ls -l 2>&1 >>out <in",
+        "echo 'a b' \"$x\" \\* $'\\n'",
+        "{ a; b & }",
+        "( a; )",
+        "for i in 1 2; do echo $i; done",
+        "case $x in a|b) x;; *);; esac",
+        "if a; then b; elif c; then d; else e; fi",
+        "while a; do b; done",
+        "f() { a; }",
+    ] {
+        let list = parse(lex(s).unwrap()).unwrap();
+        assert_eq!(list[0].0.to_string(), s);
+    }
+}